@@ -1,4 +1,9 @@
-use clap::{Parser, Subcommand};
+#[cfg(feature = "web")]
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use uuid::Uuid;
 
 #[derive(Parser, Debug)]
 #[command(name = "Safe")]
@@ -11,17 +16,50 @@ pub struct Cli {
 
     #[command(flatten)]
     pub verbosity: clap_verbosity_flag::Verbosity,
+
+    /// Read the master password from a file instead of prompting for it. The `LOCKET_PASSWORD` environment variable
+    /// can be used instead.
+    #[arg(long, global = true)]
+    pub password_file: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Subcommands {
     #[command(about = "Initialise a database and configuration")]
     Init(InitArgs),
-    New,
+    New(NewArgs),
     Query(QueryArgs),
-    Remove,
+    Remove(RemoveArgs),
+    #[command(about = "Change the details of a login")]
+    Edit,
+    #[command(about = "Copy the password of a login to the clipboard")]
+    Copy(CopyArgs),
+    #[command(about = "Find the logins for a website")]
+    Match(MatchArgs),
+    #[command(about = "Print the current one-time password for a login")]
+    Otp(OtpArgs),
+    #[command(about = "Generate a random password or passphrase")]
+    Generate(GenerateArgs),
+    #[command(about = "List the previous passwords of a login, or restore one of them")]
+    History(HistoryArgs),
+    #[command(about = "Import logins from another password manager")]
+    Import(ImportArgs),
+    #[command(
+        about = "Export every login, either in plaintext or encrypted with a separate password"
+    )]
+    Export(ExportArgs),
+    #[command(about = "Check every login for empty, reused and weak passwords")]
+    Audit(AuditArgs),
+    #[command(about = "Check every password against an offline copy of Have I Been Pwned")]
+    BreachCheck(BreachCheckArgs),
+    #[command(about = "Upgrade the database to the newest file format")]
+    Migrate(MigrateArgs),
+    #[command(
+        about = "Remove a lock left behind on the vault by an instance of Locket that crashed"
+    )]
+    Unlock(UnlockArgs),
     #[cfg(feature = "web")]
-    Serve,
+    Serve(ServeArgs),
 }
 
 #[derive(Parser, Debug)]
@@ -30,7 +68,221 @@ pub struct InitArgs {
     pub port: Option<u16>,
 }
 
+// Giving `--name` adds the login straight away, without asking for anything.
+#[derive(Parser, Debug)]
+#[command(group(ArgGroup::new("password").args(["password_stdin", "generate"])))]
+pub struct NewArgs {
+    /// The name of the login
+    #[arg(long, requires = "password")]
+    pub name: Option<String>,
+    #[arg(long, requires = "name")]
+    pub username: Option<String>,
+    /// Read the password from stdin
+    #[arg(long, requires = "name")]
+    pub password_stdin: bool,
+    /// Generate a password for the login
+    #[arg(long, requires = "name")]
+    pub generate: bool,
+    /// A website for the login, can be given more than once
+    #[arg(long = "url", value_name = "URL", requires = "name")]
+    pub urls: Vec<String>,
+    /// A tag for the login, can be given more than once
+    #[arg(long = "tag", value_name = "TAG", requires = "name")]
+    pub tags: Vec<String>,
+    #[arg(long, requires = "name")]
+    pub notes: Option<String>,
+    /// An otpauth:// URI for one-time passwords
+    #[arg(long, requires = "name")]
+    pub otp_uri: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct RemoveArgs {
+    /// The ID of the login to remove, instead of choosing it from a list
+    #[arg(long)]
+    pub id: Option<Uuid>,
+    /// Don't ask for confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
 #[derive(Parser, Debug)]
 pub struct QueryArgs {
     pub name: Option<String>,
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    pub format: Format,
+    /// Print each login using a template, e.g. '{name}\t{username}'
+    #[arg(long, conflicts_with = "format")]
+    pub template: Option<String>,
+    /// Print a single field of a single login, with nothing else, e.g. `--field password`
+    #[arg(long, conflicts_with_all = ["format", "template"])]
+    pub field: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Format {
+    Table,
+    Json,
+    Jsonl,
+    Csv,
+    Tsv,
+}
+
+#[derive(Parser, Debug)]
+pub struct CopyArgs {
+    pub name: Option<String>,
+    /// Copy this field instead of the password, e.g. `username`, or the name of a custom field
+    #[arg(long, default_value = "password")]
+    pub field: String,
+    /// Clear the clipboard after this many seconds, instead of the timeout in the configuration. 0 never clears it
+    #[arg(long, value_name = "SECONDS")]
+    pub timeout: Option<u64>,
+}
+
+#[derive(Parser, Debug)]
+pub struct MatchArgs {
+    pub url: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct OtpArgs {
+    pub name: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct HistoryArgs {
+    pub name: Option<String>,
+    /// Make the previous password with this number the current one
+    #[arg(long, value_name = "NUMBER")]
+    pub restore: Option<usize>,
+    /// Show the previous passwords instead of hiding them
+    #[arg(long, conflicts_with = "restore")]
+    pub reveal: bool,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Parser, Debug)]
+pub struct GenerateArgs {
+    /// The number of characters in the password
+    #[arg(short, long, default_value_t = crate::generator::DEFAULT_LENGTH)]
+    pub length: usize,
+    #[arg(long)]
+    pub no_lowercase: bool,
+    #[arg(long)]
+    pub no_uppercase: bool,
+    #[arg(long)]
+    pub no_digits: bool,
+    #[arg(long)]
+    pub no_symbols: bool,
+    /// Leave out characters which are easily mistaken for each other, such as `l`, `1` and `I`
+    #[arg(long)]
+    pub exclude_ambiguous: bool,
+    /// Generate a diceware passphrase with this many words (6 if it's left out), instead of a password
+    #[arg(short, long, value_name = "WORDS", conflicts_with_all = ["length", "no_lowercase", "no_uppercase", "no_digits", "no_symbols", "exclude_ambiguous"])]
+    pub words: Option<Option<usize>>,
+    /// The separator placed between the words of a passphrase
+    #[arg(long, default_value = "-", requires = "words")]
+    pub separator: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct UnlockArgs {
+    /// Remove the lock even if another instance of Locket seems to be holding it
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct ImportArgs {
+    /// The program the export came from
+    #[arg(long, value_enum)]
+    pub from: Source,
+    pub file: PathBuf,
+    /// Show what would be imported, without changing the database
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Read the password of an encrypted export from stdin
+    #[arg(long)]
+    pub password_stdin: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Source {
+    BitwardenJson,
+    KeepassCsv,
+    ChromeCsv,
+    FirefoxCsv,
+    #[value(name = "1password-csv")]
+    OnePasswordCsv,
+    // An encrypted export from `locket export --format locket`.
+    Locket,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportArgs {
+    #[arg(long, value_enum)]
+    pub format: ExportFormat,
+    /// Write the export to this file, instead of stdout
+    #[arg(short, long, required_if_eq("format", "locket"))]
+    pub output: Option<PathBuf>,
+    /// Read the password for an encrypted export from stdin
+    #[arg(long)]
+    pub password_stdin: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    BitwardenJson,
+    /// Encrypted with a password of its own, and can be imported into another database with `--from locket`
+    Locket,
+}
+
+#[derive(Parser, Debug)]
+pub struct AuditArgs {
+    #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+    pub format: ReportFormat,
+}
+
+// The SHA-1 list has to be the one ordered by hash (`pwned-passwords-sha1-ordered-by-hash.txt`), as it's binary searched.
+#[derive(Parser, Debug)]
+pub struct BreachCheckArgs {
+    #[arg(long, value_name = "FILE")]
+    pub hibp_file: PathBuf,
+    #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+    pub format: ReportFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ReportFormat {
+    Table,
+    Json,
+}
+
+#[cfg(feature = "web")]
+#[derive(Parser, Debug)]
+pub struct ServeArgs {
+    /// The address to listen on, e.g. `::1` or `192.168.1.5`, instead of the one in the config
+    #[arg(long, conflicts_with = "socket_path")]
+    pub bind: Option<IpAddr>,
+    /// The port to listen on, instead of the one in the config
+    #[arg(long, conflicts_with = "socket_path")]
+    pub port: Option<u16>,
+    /// Listen on a Unix domain socket at this path, which only you can connect to, instead of an address
+    #[arg(long, value_name = "PATH")]
+    pub socket_path: Option<PathBuf>,
+    /// Set the passphrase for logging in to the web interface, instead of using the master password
+    #[arg(long)]
+    pub set_passphrase: bool,
+    /// Read the new passphrase from stdin, rather than prompting for it
+    #[arg(long, requires = "set_passphrase")]
+    pub passphrase_stdin: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct MigrateArgs {
+    /// Report which migrations would be run, without writing anything to disk
+    #[arg(long)]
+    pub dry_run: bool,
 }
@@ -0,0 +1,140 @@
+use std::{collections::HashMap, fmt::Display};
+
+use itertools::Itertools;
+use serde_derive::Serialize;
+use tabled::Tabled;
+use uuid::Uuid;
+use zxcvbn::{zxcvbn, Score};
+
+use crate::models::Login;
+
+// Ordered from least to most severe, so that sorting in reverse puts the worst problems first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+#[derive(Debug, Serialize, Tabled)]
+pub struct Finding {
+    pub severity: Severity,
+    #[tabled(skip)]
+    pub id: Uuid,
+    #[tabled(display_with = "display_name")]
+    pub name: String,
+    pub issue: String,
+}
+
+// Checks every login for empty, reused and weak passwords, and for missing names. The worst problems come first.
+pub fn audit(logins: &HashMap<Uuid, Login>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    let mut by_password: HashMap<&str, Vec<&Login>> = HashMap::new();
+    for login in logins.values() {
+        let password = login.password.expose_secret();
+        if !password.is_empty() {
+            by_password.entry(password).or_default().push(login);
+        }
+    }
+
+    for (id, login) in logins {
+        let mut finding = |severity, issue| {
+            findings.push(Finding {
+                severity,
+                id: *id,
+                name: login.name.clone(),
+                issue,
+            });
+        };
+
+        if login.name.is_empty() {
+            finding(Severity::Low, String::from("The login has no name"));
+        }
+
+        let password = login.password.expose_secret();
+        if password.is_empty() {
+            finding(Severity::Critical, String::from("The password is empty"));
+            continue;
+        }
+
+        let others: Vec<&&Login> = by_password[password]
+            .iter()
+            .filter(|other| !std::ptr::eq(**other, login))
+            .collect();
+        if !others.is_empty() {
+            finding(
+                Severity::High,
+                format!(
+                    "The password is also used by {}",
+                    others
+                        .iter()
+                        .map(|other| format!("`{}`", other.name))
+                        .sorted()
+                        .join(", ")
+                ),
+            );
+        }
+
+        // The name and username of a login are some of the first things that somebody would try.
+        let estimate = zxcvbn(password, &[&login.name, &login.username]);
+        let severity = match estimate.score() {
+            Score::Zero | Score::One => Severity::High,
+            Score::Two => Severity::Medium,
+            _ => continue,
+        };
+        let reason = estimate
+            .feedback()
+            .and_then(zxcvbn::feedback::Feedback::warning)
+            .map(|warning| {
+                format!(
+                    ", {}",
+                    lowercase_first(warning.to_string().trim_end_matches('.'))
+                )
+            })
+            .unwrap_or_default();
+        finding(
+            severity,
+            format!(
+                "The password is weak (could be guessed in about 10^{:.0} guesses){reason}",
+                estimate.guesses_log10()
+            ),
+        );
+    }
+
+    findings.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    findings
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Low => "Low",
+            Self::Medium => "Medium",
+            Self::High => "High",
+            Self::Critical => "Critical",
+        })
+    }
+}
+
+fn display_name(name: &str) -> String {
+    if name.is_empty() {
+        String::from("(no name)")
+    } else {
+        name.to_owned()
+    }
+}
+
+fn lowercase_first(text: &str) -> String {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .map(|first| first.to_lowercase().chain(chars).collect())
+        .unwrap_or_default()
+}
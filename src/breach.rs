@@ -0,0 +1,147 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Seek, SeekFrom},
+    path::Path,
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use data_encoding::HEXUPPER;
+use serde_derive::Serialize;
+use sha1::{Digest, Sha1};
+use tabled::Tabled;
+use uuid::Uuid;
+
+use crate::models::Login;
+
+#[derive(Debug, Serialize, Tabled)]
+pub struct Breach {
+    #[tabled(skip)]
+    pub id: Uuid,
+    pub name: String,
+    pub username: String,
+    // How many times the password turns up in the breaches that Have I Been Pwned knows about.
+    #[tabled(rename = "times seen")]
+    pub count: u64,
+}
+
+// The SHA-1 version of Have I Been Pwned's Pwned Passwords list, ordered by hash. Every line looks like
+// `<40 hex digits>:<count>`, and there's a billion or so of them, so rather than reading the whole thing, it's binary
+// searched straight from the disk.
+pub struct HibpFile {
+    reader: BufReader<File>,
+    len: u64,
+}
+
+impl HibpFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).wrap_err("Failed to open the HIBP file")?;
+        let len = file
+            .metadata()
+            .wrap_err("Failed to read the metadata of the HIBP file")?
+            .len();
+        Ok(Self {
+            reader: BufReader::new(file),
+            len,
+        })
+    }
+
+    // How many times a password has been seen, or `None` if it never has.
+    pub fn count(&mut self, password: &str) -> Result<Option<u64>> {
+        let hash = HEXUPPER.encode(&Sha1::digest(password.as_bytes()));
+
+        let mut low = 0;
+        let mut high = self.len;
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let Some((end, line)) = self.line_from(middle)? else {
+                // There's no line starting after the middle, so the hash can only be in the first half.
+                high = middle;
+                continue;
+            };
+
+            let Some((line_hash, count)) = line.split_once(':') else {
+                bail!("The HIBP file has a line without a `:`, is it the SHA-1 list?");
+            };
+            match line_hash.to_ascii_uppercase().as_str().cmp(&hash) {
+                Ordering::Equal => {
+                    return count
+                        .trim()
+                        .parse()
+                        .map(Some)
+                        .wrap_err("The HIBP file has a count which isn't a number");
+                }
+                Ordering::Less => low = end,
+                Ordering::Greater => high = middle,
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Reads the first line that starts at or after `position`, returning it along with where the next one starts.
+    fn line_from(&mut self, position: u64) -> Result<Option<(u64, String)>> {
+        let mut start = position;
+        if position > 0 {
+            // Reading from the byte before means that a line starting exactly at `position` isn't skipped.
+            self.reader
+                .seek(SeekFrom::Start(position - 1))
+                .wrap_err("Failed to seek in the HIBP file")?;
+            let mut skipped = Vec::new();
+            start += self
+                .reader
+                .read_until(b'\n', &mut skipped)
+                .wrap_err("Failed to read the HIBP file")? as u64
+                - 1;
+        } else {
+            self.reader
+                .seek(SeekFrom::Start(0))
+                .wrap_err("Failed to seek in the HIBP file")?;
+        }
+
+        let mut line = String::new();
+        let read = self
+            .reader
+            .read_line(&mut line)
+            .wrap_err("Failed to read the HIBP file")?;
+        if read == 0 {
+            return Ok(None);
+        }
+        Ok(Some((start + read as u64, line.trim_end().to_owned())))
+    }
+}
+
+// Looks up every password in the HIBP file, returning the logins whose passwords were found, most seen first.
+pub fn check(logins: &HashMap<Uuid, Login>, path: &Path) -> Result<Vec<Breach>> {
+    let mut file = HibpFile::open(path)?;
+
+    // Reused passwords only need to be looked up once.
+    let mut counts: HashMap<&str, Option<u64>> = HashMap::new();
+    let mut breaches = Vec::new();
+    for (id, login) in logins {
+        let password = login.password.expose_secret();
+        if password.is_empty() {
+            continue;
+        }
+        let count = if let Some(count) = counts.get(password) {
+            *count
+        } else {
+            let count = file.count(password)?;
+            counts.insert(password, count);
+            count
+        };
+
+        if let Some(count) = count {
+            breaches.push(Breach {
+                id: *id,
+                name: login.name.clone(),
+                username: login.username.clone(),
+                count,
+            });
+        }
+    }
+
+    breaches.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    Ok(breaches)
+}
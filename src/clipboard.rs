@@ -0,0 +1,93 @@
+use std::{
+    env,
+    fs::OpenOptions,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use data_encoding::BASE64;
+use serde_derive::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+// How the clipboard is reached. In `locket.toml` this is either `clipboard = "osc52"` (or `"wl-copy"`, `"xclip"`), or
+// `clipboard = { command = ["pbcopy"] }` for anything else, which is given the text on stdin.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    // An escape sequence which asks the terminal to set the clipboard. This works over SSH, but not every terminal
+    // supports it.
+    #[default]
+    Osc52,
+    WlCopy,
+    Xclip,
+    Command(Vec<String>),
+}
+
+impl Backend {
+    pub fn copy(&self, text: &str) -> Result<()> {
+        match self {
+            Self::Osc52 => osc52(text),
+            Self::WlCopy => pipe(&["wl-copy"], text),
+            Self::Xclip => pipe(&["xclip", "-selection", "clipboard"], text),
+            Self::Command(command) => pipe(command, text),
+        }
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        match self {
+            Self::WlCopy => pipe(&["wl-copy", "--clear"], ""),
+            _ => self.copy(""),
+        }
+    }
+}
+
+fn osc52(text: &str) -> Result<()> {
+    let encoded = Zeroizing::new(BASE64.encode(text.as_bytes()));
+    let mut sequence = Zeroizing::new(format!("\x1b]52;c;{}\x07", *encoded));
+    // tmux swallows escape sequences it doesn't know about, unless they're wrapped up like this (and
+    // `allow-passthrough` is on).
+    if env::var_os("TMUX").is_some() {
+        sequence = Zeroizing::new(format!(
+            "\x1bPtmux;{}\x1b\\",
+            sequence.replace('\x1b', "\x1b\x1b")
+        ));
+    }
+
+    // Going straight to the terminal means that this still works when stdout is redirected.
+    let mut tty = OpenOptions::new()
+        .write(true)
+        .open("/dev/tty")
+        .wrap_err("Failed to open the terminal, OSC 52 can only be used from a terminal")?;
+    tty.write_all(sequence.as_bytes())
+        .wrap_err("Failed to write to the terminal")?;
+    tty.flush().wrap_err("Failed to write to the terminal")
+}
+
+fn pipe<S: AsRef<str>>(command: &[S], text: &str) -> Result<()> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("The clipboard command is empty");
+    };
+    let program = program.as_ref();
+
+    let mut child = Command::new(program)
+        .args(args.iter().map(AsRef::as_ref))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .wrap_err_with(|| format!("Failed to run `{program}`"))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin was piped")
+        .write_all(text.as_bytes())
+        .wrap_err_with(|| format!("Failed to write to `{program}`"))?;
+
+    let status = child
+        .wait()
+        .wrap_err_with(|| format!("Failed to wait for `{program}`"))?;
+    if !status.success() {
+        bail!("`{program}` failed with {status}");
+    }
+    Ok(())
+}
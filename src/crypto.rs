@@ -0,0 +1,219 @@
+use std::fmt::Debug;
+
+#[cfg(feature = "web")]
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use color_eyre::eyre::{bail, eyre, Result};
+use serde_derive::{Deserialize, Serialize};
+use serde_with::{serde_as, Bytes};
+use zeroize::Zeroizing;
+
+use crate::errors::LocketError;
+
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const CHECK_LEN: usize = 32;
+
+// These are the parameters which are passed to Argon2id. They're stored alongside the ciphertext, so that they can
+// be increased in the future without breaking existing databases.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+// What actually gets written to disk. The plaintext is a MessagePack encoded `Database`, sealed with
+// XChaCha20-Poly1305.
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+pub struct Envelope {
+    kdf: KdfParams,
+    #[serde_as(as = "Bytes")]
+    salt: [u8; SALT_LEN],
+    #[serde_as(as = "Bytes")]
+    check: [u8; CHECK_LEN],
+    #[serde_as(as = "Bytes")]
+    nonce: [u8; 24],
+    #[serde_as(as = "Bytes")]
+    ciphertext: Vec<u8>,
+}
+
+// Holds the key derived from the master password for as long as the database is open, so that we don't have to ask
+// for the password again (or keep it around) when syncing.
+pub struct Cipher {
+    kdf: KdfParams,
+    salt: [u8; SALT_LEN],
+    key: Zeroizing<[u8; KEY_LEN]>,
+    // The second half of the Argon2 output. It's stored in the envelope so that we can tell a wrong password apart
+    // from a file that has been tampered with, without having to keep a copy of the password around.
+    check: [u8; CHECK_LEN],
+}
+
+// The parameters are read from the file before anything in it can be checked, so anything outside of these is taken
+// to mean that the file is corrupt, rather than having Argon2 try to allocate terabytes or run for days.
+const MIN_MEMORY_KIB: u32 = 8 * 1024;
+const MAX_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+const MAX_ITERATIONS: u32 = 64;
+const MAX_PARALLELISM: u32 = 16;
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    fn is_sane(&self) -> bool {
+        (MIN_MEMORY_KIB..=MAX_MEMORY_KIB).contains(&self.memory_kib)
+            && (1..=MAX_ITERATIONS).contains(&self.iterations)
+            && (1..=MAX_PARALLELISM).contains(&self.parallelism)
+    }
+}
+
+impl Cipher {
+    pub fn new(password: &str) -> Result<Self> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        Self::derive(password, KdfParams::default(), salt)
+    }
+
+    fn derive(password: &str, kdf: KdfParams, salt: [u8; SALT_LEN]) -> Result<Self> {
+        if !kdf.is_sane() {
+            bail!(LocketError::CorruptDatabaseError);
+        }
+        let params = Params::new(
+            kdf.memory_kib,
+            kdf.iterations,
+            kdf.parallelism,
+            Some(KEY_LEN + CHECK_LEN),
+        )
+        .map_err(|e| eyre!("Invalid key derivation parameters: {e}"))?;
+
+        let mut output = Zeroizing::new([0; KEY_LEN + CHECK_LEN]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), &salt, output.as_mut())
+            .map_err(|e| eyre!("Failed to derive a key from the master password: {e}"))?;
+
+        let mut key = Zeroizing::new([0; KEY_LEN]);
+        key.copy_from_slice(&output[..KEY_LEN]);
+        let mut check = [0; CHECK_LEN];
+        check.copy_from_slice(&output[KEY_LEN..]);
+
+        Ok(Self {
+            kdf,
+            salt,
+            key,
+            check,
+        })
+    }
+
+    // Derives the key for an envelope, and decrypts its contents. `header` is whatever the envelope was written after
+    // in the file, or `None` for a file from before that was authenticated too, when only the salt was.
+    pub fn open(
+        envelope: &Envelope,
+        password: &str,
+        header: Option<&[u8]>,
+    ) -> Result<(Self, Zeroizing<Vec<u8>>)> {
+        let cipher = Self::derive(password, envelope.kdf, envelope.salt)?;
+        if !constant_time_eq(&cipher.check, &envelope.check) {
+            bail!(LocketError::WrongPasswordError);
+        }
+
+        let aad = match header {
+            Some(header) => associated_data(header, &envelope.kdf, &envelope.salt, &envelope.check),
+            None => envelope.salt.to_vec(),
+        };
+        let plaintext = XChaCha20Poly1305::new(cipher.key.as_ref().into())
+            .decrypt(
+                XNonce::from_slice(&envelope.nonce),
+                Payload {
+                    msg: &envelope.ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| LocketError::CorruptDatabaseError)?;
+
+        Ok((cipher, Zeroizing::new(plaintext)))
+    }
+
+    // Checks a password against the one this key was derived from, by deriving it again.
+    #[cfg(feature = "web")]
+    pub fn verify_password(&self, password: &str) -> Result<bool> {
+        let other = Self::derive(password, self.kdf, self.salt)?;
+        Ok(constant_time_eq(&self.check, &other.check))
+    }
+
+    // `header` is whatever the envelope is going to be written after in the file.
+    pub fn seal(&self, plaintext: &[u8], header: &[u8]) -> Result<Envelope> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = XChaCha20Poly1305::new(self.key.as_ref().into())
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: &associated_data(header, &self.kdf, &self.salt, &self.check),
+                },
+            )
+            .map_err(|e| eyre!("Failed to encrypt the database: {e}"))?;
+
+        Ok(Envelope {
+            kdf: self.kdf,
+            salt: self.salt,
+            check: self.check,
+            nonce: nonce.into(),
+            ciphertext,
+        })
+    }
+}
+
+impl Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cipher")
+            .field("kdf", &self.kdf)
+            .finish_non_exhaustive()
+    }
+}
+
+// Everything in the file apart from the ciphertext is authenticated along with it, so that none of it can be changed
+// without decryption failing. The nonce is left out, since the cipher depends on it anyway.
+fn associated_data(header: &[u8], kdf: &KdfParams, salt: &[u8], check: &[u8]) -> Vec<u8> {
+    let mut aad = header.to_vec();
+    for param in [kdf.memory_kib, kdf.iterations, kdf.parallelism] {
+        aad.extend_from_slice(&param.to_le_bytes());
+    }
+    aad.extend_from_slice(salt);
+    aad.extend_from_slice(check);
+    aad
+}
+
+// Hashes a password into a PHC string (`$argon2id$...`), which has everything needed to check it again later.
+#[cfg(feature = "web")]
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| eyre!("Failed to hash the password: {e}"))
+}
+
+#[cfg(feature = "web")]
+pub fn verify_password_hash(hash: &str, password: &str) -> Result<bool> {
+    let hash = PasswordHash::new(hash).map_err(|e| eyre!("The password hash is invalid: {e}"))?;
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok())
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,42 @@
+use color_eyre::eyre::{bail, Context, Result};
+use url::{Host, Url};
+
+// Parses a website URL as it would be typed by a person, so `example.com` is accepted as well as
+// `https://example.com/login`. Only `http` and `https` URLs with a host are accepted, as anything else, like
+// `javascript://example.com/%0aalert(1)`, could do something other than open a website when it's clicked.
+pub fn parse_url(input: &str) -> Result<Url> {
+    let input = input.trim();
+    let url = if input.contains("://") {
+        Url::parse(input)
+    } else {
+        Url::parse(&format!("https://{input}"))
+    }
+    .wrap_err_with(|| format!("`{input}` is not a valid URL"))?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!("`{input}` isn't a website, only `http` and `https` URLs are allowed");
+    }
+    if url.host().is_none() {
+        bail!("`{input}` doesn't contain a host");
+    }
+
+    Ok(url)
+}
+
+// Whether two URLs belong to the same site, i.e. they share a registrable domain according to the Public Suffix
+// List. `accounts.example.co.uk` and `www.example.co.uk` are the same site, but `example.co.uk` and
+// `other.co.uk` aren't, even though they share `co.uk`. IP addresses, and hosts which are themselves a public
+// suffix, only ever match exactly.
+pub fn same_site(a: &Url, b: &Url) -> bool {
+    match (a.host(), b.host()) {
+        (Some(Host::Domain(a)), Some(Host::Domain(b))) => {
+            let (a, b) = (a.trim_end_matches('.'), b.trim_end_matches('.'));
+            match (psl::domain_str(a), psl::domain_str(b)) {
+                (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+                _ => a.eq_ignore_ascii_case(b),
+            }
+        }
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
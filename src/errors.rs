@@ -1,9 +1,22 @@
 use thiserror::Error;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Copy, Clone, Error)]
 pub enum LocketError {
     #[error("Tried to initialise a configuration file where one already exists")]
     ConfigAlreadyExistsError,
     #[error("Tried to initialise a database file where one already exists")]
     DatabaseAlreadyExistsError,
+    #[error("The master password is incorrect")]
+    WrongPasswordError,
+    #[error("The database could not be decrypted, it is either corrupt or has been tampered with")]
+    CorruptDatabaseError,
+    #[error("The database was written by a newer version of Locket, please upgrade to open it")]
+    UnsupportedVersionError,
+    #[error("The database isn't encrypted, please run `locket migrate` to encrypt it with the master password")]
+    UnencryptedDatabaseError,
+    #[error("Locket hasn't been initialised yet, please run `locket init` first")]
+    NotInitialisedError,
+    #[error("Refusing to prompt because stdin isn't a terminal, pass the missing values as flags instead (see `--help`)")]
+    NotATerminalError,
 }
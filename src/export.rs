@@ -0,0 +1,121 @@
+use std::io::Write;
+
+use color_eyre::eyre::{Context, Result};
+use itertools::Itertools;
+use serde_derive::Serialize;
+use uuid::Uuid;
+
+use crate::models::{FieldValue, Login};
+
+// Bitwarden's unencrypted JSON export, with just enough in it for Bitwarden (and everything else which understands
+// the format) to import it. It's the same format that `locket import --from bitwarden-json` reads.
+#[derive(Serialize)]
+struct BitwardenExport<'a> {
+    encrypted: bool,
+    folders: Vec<BitwardenFolder<'a>>,
+    items: Vec<BitwardenItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct BitwardenFolder<'a> {
+    id: Uuid,
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BitwardenItem<'a> {
+    id: &'a Uuid,
+    folder_id: Option<Uuid>,
+    #[serde(rename = "type")]
+    kind: u8,
+    name: &'a str,
+    notes: Option<&'a str>,
+    favorite: bool,
+    fields: Vec<BitwardenField<'a>>,
+    login: BitwardenLogin<'a>,
+}
+
+#[derive(Serialize)]
+struct BitwardenField<'a> {
+    name: &'a str,
+    value: String,
+    #[serde(rename = "type")]
+    kind: u8,
+}
+
+#[derive(Serialize)]
+struct BitwardenLogin<'a> {
+    username: &'a str,
+    password: &'a str,
+    totp: Option<String>,
+    uris: Vec<BitwardenUri<'a>>,
+}
+
+#[derive(Serialize)]
+struct BitwardenUri<'a> {
+    uri: &'a str,
+}
+
+pub fn write_bitwarden<W: Write>(writer: W, logins: &[(&Uuid, &Login)]) -> Result<()> {
+    // Bitwarden only has folders, so the first tag of each login becomes its folder.
+    let folders: Vec<BitwardenFolder> = logins
+        .iter()
+        .filter_map(|(_, login)| login.tags.first())
+        .unique()
+        .map(|name| BitwardenFolder {
+            id: Uuid::new_v4(),
+            name,
+        })
+        .collect();
+
+    let items = logins
+        .iter()
+        .map(|(id, login)| BitwardenItem {
+            id,
+            folder_id: login.tags.first().and_then(|tag| {
+                folders
+                    .iter()
+                    .find(|folder| folder.name == tag)
+                    .map(|folder| folder.id)
+            }),
+            // A login, as opposed to a secure note, card or identity.
+            kind: 1,
+            name: &login.name,
+            notes: Some(login.notes.as_str()).filter(|notes| !notes.is_empty()),
+            favorite: false,
+            fields: login
+                .fields
+                .iter()
+                .map(|field| {
+                    let (value, kind) = match &field.value {
+                        FieldValue::Text(text) | FieldValue::Url(text) => (text.clone(), 0),
+                        FieldValue::Hidden(secret) => (secret.expose_secret().to_owned(), 1),
+                        FieldValue::Date(date) => (date.to_string(), 0),
+                    };
+                    BitwardenField {
+                        name: &field.name,
+                        value,
+                        kind,
+                    }
+                })
+                .collect(),
+            login: BitwardenLogin {
+                username: &login.username,
+                password: login.password.expose_secret(),
+                totp: login.otp.as_ref().map(crate::otp::Otp::to_uri),
+                uris: login.urls.iter().map(|uri| BitwardenUri { uri }).collect(),
+            },
+        })
+        .collect();
+
+    serde_json::to_writer_pretty(
+        writer,
+        &BitwardenExport {
+            encrypted: false,
+            folders,
+            items,
+        },
+    )
+    .wrap_err("Failed to write the logins as JSON")
+}
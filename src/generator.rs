@@ -0,0 +1,153 @@
+use color_eyre::eyre::{bail, Result};
+use rand::{rngs::OsRng, seq::SliceRandom};
+
+use crate::args::GenerateArgs;
+use crate::secret::SecretString;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!@#$%^&*()-_=+[]{};:,.<>/?~";
+// Characters which are easily confused with each other in many fonts.
+const AMBIGUOUS: &str = "Il1|O0o";
+
+// The EFF's large wordlist, which has 7776 (6^5) words, one for every roll of five dice.
+static WORDLIST: &str = include_str!("wordlist/eff_large_wordlist.txt");
+
+pub const DEFAULT_LENGTH: usize = 24;
+pub const DEFAULT_WORDS: usize = 6;
+// Far more than anybody needs, but small enough that a silly request, e.g. from the web interface, can't use up all of
+// our memory.
+pub const MAX_LENGTH: usize = 1024;
+pub const MAX_WORDS: usize = 64;
+
+#[derive(Debug, Clone)]
+pub enum Spec {
+    Password {
+        length: usize,
+        lowercase: bool,
+        uppercase: bool,
+        digits: bool,
+        symbols: bool,
+        exclude_ambiguous: bool,
+    },
+    Passphrase {
+        words: usize,
+        separator: String,
+    },
+}
+
+#[derive(Debug)]
+pub struct Generated {
+    pub password: SecretString,
+    // How many bits of entropy went into the password, assuming that an attacker knows exactly how it was generated.
+    pub entropy: f64,
+}
+
+impl Default for Spec {
+    fn default() -> Self {
+        Self::Password {
+            length: DEFAULT_LENGTH,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            exclude_ambiguous: false,
+        }
+    }
+}
+
+impl From<&GenerateArgs> for Spec {
+    fn from(args: &GenerateArgs) -> Self {
+        match args.words {
+            Some(words) => Self::Passphrase {
+                words: words.unwrap_or(DEFAULT_WORDS),
+                separator: args.separator.clone(),
+            },
+            None => Self::Password {
+                length: args.length,
+                lowercase: !args.no_lowercase,
+                uppercase: !args.no_uppercase,
+                digits: !args.no_digits,
+                symbols: !args.no_symbols,
+                exclude_ambiguous: args.exclude_ambiguous,
+            },
+        }
+    }
+}
+
+pub fn generate(spec: &Spec) -> Result<Generated> {
+    match spec {
+        Spec::Password {
+            length,
+            lowercase,
+            uppercase,
+            digits,
+            symbols,
+            exclude_ambiguous,
+        } => {
+            if *length == 0 {
+                bail!("The length of a password must be at least 1");
+            }
+            if *length > MAX_LENGTH {
+                bail!("The length of a password can be at most {MAX_LENGTH}");
+            }
+
+            let alphabet: Vec<char> = [
+                (*lowercase, LOWERCASE),
+                (*uppercase, UPPERCASE),
+                (*digits, DIGITS),
+                (*symbols, SYMBOLS),
+            ]
+            .into_iter()
+            .filter(|(enabled, _)| *enabled)
+            .flat_map(|(_, class)| class.chars())
+            .filter(|c| !(*exclude_ambiguous && AMBIGUOUS.contains(*c)))
+            .collect();
+            if alphabet.is_empty() {
+                bail!("At least one character class must be enabled");
+            }
+
+            let password: String = (0..*length)
+                .map(|_| *alphabet.choose(&mut OsRng).expect("Checked above"))
+                .collect();
+
+            Ok(Generated {
+                password: SecretString::new(password),
+                entropy: entropy(alphabet.len(), *length),
+            })
+        }
+        Spec::Passphrase { words, separator } => {
+            if *words == 0 {
+                bail!("A passphrase must contain at least 1 word");
+            }
+            if *words > MAX_WORDS {
+                bail!("A passphrase can contain at most {MAX_WORDS} words");
+            }
+
+            let wordlist: Vec<&str> = WORDLIST
+                .lines()
+                .filter_map(|line| line.split_once('\t').map(|(_, word)| word))
+                .collect();
+
+            let passphrase = (0..*words)
+                .map(|_| {
+                    *wordlist
+                        .choose(&mut OsRng)
+                        .expect("The wordlist isn't empty")
+                })
+                .collect::<Vec<_>>()
+                .join(separator);
+
+            Ok(Generated {
+                password: SecretString::new(passphrase),
+                entropy: entropy(wordlist.len(), *words),
+            })
+        }
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn entropy(choices: usize, picks: usize) -> f64 {
+    picks as f64 * (choices as f64).log2()
+}
@@ -0,0 +1,394 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use color_eyre::eyre::{bail, Context, Result};
+use serde_derive::Deserialize;
+use uuid::Uuid;
+
+use crate::args::Source;
+use crate::domain;
+use crate::models::{CustomField, Database, FieldValue, Login};
+use crate::otp::Otp;
+use crate::secret::SecretString;
+
+// One row of an export. `location` says where it came from (e.g. `line 12`), so that problems can be reported in a
+// way that can be found in the original file.
+pub struct Entry {
+    pub location: String,
+    // Only encrypted exports from Locket itself have IDs, which are kept so that nothing changes on a round trip.
+    pub id: Option<Uuid>,
+    pub login: Result<Login>,
+    // Anything which couldn't be imported, but which wasn't worth skipping the whole row over.
+    pub warnings: Vec<String>,
+}
+
+// `password` is only needed for encrypted exports.
+pub fn read(source: Source, path: &Path, password: Option<&str>) -> Result<Vec<Entry>> {
+    match source {
+        Source::Locket => {
+            let Some(password) = password else {
+                bail!("An encrypted export can't be read without its password");
+            };
+            read_locket(path, password)
+        }
+        Source::BitwardenJson => read_bitwarden(path),
+        Source::KeepassCsv => read_csv(path, &KEEPASS),
+        Source::ChromeCsv => read_csv(path, &CHROME),
+        Source::FirefoxCsv => read_csv(path, &FIREFOX),
+        Source::OnePasswordCsv => read_csv(path, &ONE_PASSWORD),
+    }
+}
+
+// The headers each field can be found under, in lowercase. Exports from different versions of the same program don't
+// always agree, so there's often more than one.
+struct Columns {
+    name: &'static [&'static str],
+    username: &'static [&'static str],
+    password: &'static [&'static str],
+    url: &'static [&'static str],
+    notes: &'static [&'static str],
+    otp: &'static [&'static str],
+    tags: &'static [&'static str],
+}
+
+// KeePassXC, with the KeePass 2 names as fallbacks.
+static KEEPASS: Columns = Columns {
+    name: &["title", "account"],
+    username: &["username", "login name", "user name"],
+    password: &["password"],
+    url: &["url", "web site"],
+    notes: &["notes", "comments"],
+    otp: &["totp"],
+    tags: &["group"],
+};
+
+static CHROME: Columns = Columns {
+    name: &["name"],
+    username: &["username"],
+    password: &["password"],
+    url: &["url"],
+    notes: &["note", "notes"],
+    otp: &[],
+    tags: &[],
+};
+
+// Firefox doesn't name its logins, so they're named after their website instead.
+static FIREFOX: Columns = Columns {
+    name: &[],
+    username: &["username"],
+    password: &["password"],
+    url: &["url"],
+    notes: &[],
+    otp: &[],
+    tags: &[],
+};
+
+static ONE_PASSWORD: Columns = Columns {
+    name: &["title", "name"],
+    username: &["username"],
+    password: &["password"],
+    url: &["url", "website", "urls"],
+    notes: &["notes", "notesplain"],
+    otp: &["otpauth", "one-time password"],
+    tags: &["tags"],
+};
+
+// An encrypted export is just a database with a different password, but it's decoded without any of the leniency of
+// opening the vault itself, e.g. an empty file is an error rather than an export without any logins.
+fn read_locket(path: &Path, password: &str) -> Result<Vec<Entry>> {
+    let buf = fs::read(path).wrap_err("Failed to read the export")?;
+    let export = Database::decode_export(&buf, password).wrap_err("Failed to open the export")?;
+    Ok(export
+        .logins
+        .into_iter()
+        .map(|(id, login)| Entry {
+            location: format!("`{}` ({id})", login.name),
+            id: Some(id),
+            login: Ok(login),
+            warnings: Vec::new(),
+        })
+        .collect())
+}
+
+fn read_csv(path: &Path, columns: &Columns) -> Result<Vec<Entry>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_path(path)
+        .wrap_err("Failed to open the export")?;
+    let headers: HashMap<String, usize> = reader
+        .headers()
+        .wrap_err("Failed to read the header of the export")?
+        .iter()
+        .enumerate()
+        .map(|(index, header)| (header.trim().to_lowercase(), index))
+        .collect();
+    let find = |names: &[&str]| names.iter().find_map(|name| headers.get(*name).copied());
+
+    let Some(password_column) = find(columns.password) else {
+        bail!("The export has no password column, is it the right format?");
+    };
+    let name_column = find(columns.name);
+    let username_column = find(columns.username);
+    let url_column = find(columns.url);
+    let notes_column = find(columns.notes);
+    let otp_column = find(columns.otp);
+    let tags_column = find(columns.tags);
+
+    let mut entries = Vec::new();
+    for record in reader.records() {
+        // A row that can't be read (e.g. because it isn't UTF-8) is skipped like any other bad row. If the file itself
+        // can't be read, there's no point in carrying on.
+        let record = match record {
+            Ok(record) => record,
+            Err(e) if e.is_io_error() => {
+                return Err(e).wrap_err("Failed to read a row of the export")
+            }
+            Err(e) => {
+                entries.push(Entry {
+                    location: format!("line {}", e.position().map_or(0, csv::Position::line)),
+                    id: None,
+                    login: Err(e).wrap_err("It couldn't be read"),
+                    warnings: Vec::new(),
+                });
+                continue;
+            }
+        };
+        let location = format!("line {}", record.position().map_or(0, csv::Position::line));
+        let get = |column: Option<usize>| {
+            column
+                .and_then(|column| record.get(column))
+                .unwrap_or_default()
+                .trim()
+        };
+
+        let mut warnings = Vec::new();
+        let url = get(url_column);
+        let login = parse_row(
+            Row {
+                name: get(name_column),
+                username: get(username_column),
+                password: get(Some(password_column)),
+                urls: url.split_whitespace().collect(),
+                notes: get(notes_column),
+                otp: get(otp_column),
+                tags: get(tags_column)
+                    .split(',')
+                    .map(str::trim)
+                    // KeePass puts everything under a `Root` group, which isn't worth having as a tag.
+                    .map(|tag| tag.strip_prefix("Root/").unwrap_or(tag))
+                    .filter(|tag| !tag.is_empty() && *tag != "Root")
+                    .collect(),
+                fields: Vec::new(),
+            },
+            &mut warnings,
+        );
+
+        entries.push(Entry {
+            location,
+            id: None,
+            login,
+            warnings,
+        });
+    }
+
+    Ok(entries)
+}
+
+// The parts of Bitwarden's unencrypted JSON export which mean anything to us.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BitwardenExport {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    folders: Vec<BitwardenFolder>,
+    #[serde(default)]
+    items: Vec<BitwardenItem>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenFolder {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BitwardenItem {
+    #[serde(rename = "type")]
+    kind: u8,
+    #[serde(default)]
+    name: String,
+    notes: Option<String>,
+    folder_id: Option<String>,
+    #[serde(default)]
+    fields: Vec<BitwardenField>,
+    login: Option<BitwardenLogin>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenField {
+    name: Option<String>,
+    value: Option<String>,
+    #[serde(rename = "type")]
+    kind: u8,
+}
+
+#[derive(Deserialize)]
+struct BitwardenLogin {
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+    #[serde(default)]
+    uris: Vec<BitwardenUri>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenUri {
+    uri: Option<String>,
+}
+
+fn read_bitwarden(path: &Path) -> Result<Vec<Entry>> {
+    let content = fs::read_to_string(path).wrap_err("Failed to read the export")?;
+    let export: BitwardenExport =
+        serde_json::from_str(&content).wrap_err("Failed to parse the export")?;
+    if export.encrypted {
+        bail!("Encrypted Bitwarden exports can't be imported, please export it unencrypted");
+    }
+    let folders: HashMap<&str, &str> = export
+        .folders
+        .iter()
+        .map(|folder| (folder.id.as_str(), folder.name.as_str()))
+        .collect();
+
+    let mut entries = Vec::new();
+    for (index, item) in export.items.iter().enumerate() {
+        let location = format!("item {} (`{}`)", index + 1, item.name);
+        let mut warnings = Vec::new();
+
+        // 1 is a login, the rest are secure notes, cards and identities.
+        let Some(login) = item.login.as_ref().filter(|_| item.kind == 1) else {
+            entries.push(Entry {
+                location,
+                id: None,
+                login: Err(color_eyre::eyre::eyre!("It isn't a login")),
+                warnings,
+            });
+            continue;
+        };
+
+        let mut fields = Vec::new();
+        for field in &item.fields {
+            let name = field.name.clone().unwrap_or_default();
+            let value = field.value.clone().unwrap_or_default();
+            fields.push(CustomField {
+                value: match field.kind {
+                    0 | 2 => FieldValue::Text(value),
+                    1 => FieldValue::Hidden(SecretString::new(value)),
+                    _ => {
+                        warnings.push(format!("The linked field `{name}` was left out"));
+                        continue;
+                    }
+                },
+                name,
+            });
+        }
+
+        let login = parse_row(
+            Row {
+                name: &item.name,
+                username: login.username.as_deref().unwrap_or_default(),
+                password: login.password.as_deref().unwrap_or_default(),
+                urls: login
+                    .uris
+                    .iter()
+                    .filter_map(|uri| uri.uri.as_deref())
+                    .collect(),
+                notes: item.notes.as_deref().unwrap_or_default(),
+                otp: login.totp.as_deref().unwrap_or_default(),
+                tags: item
+                    .folder_id
+                    .as_deref()
+                    .and_then(|id| folders.get(id))
+                    .copied()
+                    .into_iter()
+                    .collect(),
+                fields,
+            },
+            &mut warnings,
+        );
+
+        entries.push(Entry {
+            location,
+            id: None,
+            login,
+            warnings,
+        });
+    }
+
+    Ok(entries)
+}
+
+// Everything that's needed to make a `Login`, after it's been dug out of whichever format it came from.
+struct Row<'a> {
+    name: &'a str,
+    username: &'a str,
+    password: &'a str,
+    urls: Vec<&'a str>,
+    notes: &'a str,
+    otp: &'a str,
+    tags: Vec<&'a str>,
+    fields: Vec<CustomField>,
+}
+
+fn parse_row(row: Row, warnings: &mut Vec<String>) -> Result<Login> {
+    if row.name.is_empty() && row.username.is_empty() && row.password.is_empty() {
+        bail!("It's empty");
+    }
+
+    let mut login = Login::new(
+        String::from(row.name),
+        String::from(row.username),
+        SecretString::new(String::from(row.password)),
+    );
+    // A website that doesn't make sense isn't worth losing the rest of the login over.
+    login.urls = row
+        .urls
+        .into_iter()
+        .filter_map(|url| match domain::parse_url(url) {
+            Ok(url) => Some(String::from(url)),
+            Err(e) => {
+                warnings.push(format!("The website was left out: {e}"));
+                None
+            }
+        })
+        .collect();
+    login.notes = String::from(row.notes);
+    login.tags = row.tags.into_iter().map(String::from).collect();
+    login.fields = row.fields;
+
+    if login.name.is_empty() {
+        if let Some(host) = login
+            .urls
+            .first()
+            .and_then(|url| url::Url::parse(url).ok())
+            .and_then(|url| url.host_str().map(String::from))
+        {
+            login.name = host;
+        }
+    }
+
+    if !row.otp.is_empty() {
+        // Some programs only store the secret, rather than a whole URI.
+        let otp = if row.otp.starts_with("otpauth://") {
+            Otp::from_uri(row.otp)
+        } else {
+            Otp::from_uri(&format!("otpauth://totp/import?secret={}", row.otp))
+        };
+        match otp {
+            Ok(otp) => login.otp = Some(otp),
+            Err(e) => warnings.push(format!("The one-time password was left out: {e}")),
+        }
+    }
+
+    Ok(login)
+}
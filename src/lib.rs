@@ -1,36 +1,70 @@
 #![warn(clippy::all)]
 #![warn(clippy::pedantic)]
 
-use std::{env, fs, fs::OpenOptions, hint::unreachable_unchecked, io::ErrorKind};
+use std::{env, fs, hint::unreachable_unchecked, path::Path, thread, time::Duration};
 
-use color_eyre::eyre::bail;
 use color_eyre::{eyre::Context, Result};
 
 pub mod args;
+mod audit;
+mod breach;
+mod clipboard;
+mod crypto;
+mod domain;
 mod errors;
+mod export;
+mod generator;
+mod import;
+mod lock;
+mod migrations;
 mod models;
 #[cfg(feature = "web")]
 mod net;
+mod otp;
+mod output;
+mod secret;
+#[cfg(feature = "web")]
+mod session;
+#[cfg(feature = "web")]
+mod threadpool;
+#[cfg(feature = "web")]
+mod tls;
+mod vault;
 
-use crate::args::InitArgs;
+// Everything needed to use a vault from another crate, without going through the CLI.
+pub use crate::errors::LocketError;
+pub use crate::models::{CustomField, FieldValue, Login, LoginPatch, PreviousPassword};
+pub use crate::secret::SecretString;
+pub use crate::vault::{Locket, Options, Session};
+
+use crate::args::{ImportArgs, InitArgs, MigrateArgs, UnlockArgs};
 use crate::models::Config;
 use args::Cli;
 use models::Database;
 
-static DATABASE_FILE_NAME: &str = "locket.db";
-static CONFIG_FILE_NAME: &str = "locket.toml";
-static LCK_FILE_NAME: &str = "locket.lck";
+static PASSWORD_ENV_VAR: &str = "LOCKET_PASSWORD";
 
-// TODO: Extract the logic of opening and closing the config, database, and lockfile into either a set of functions, or an empty struct called
-// `Program` or something, which is responsible for all of this stuff. That would also improve the shutdown logic in `net::serve()`, and would
-// ensure that both functions stayed up to date. This is not especially urgent since it's just another abstraction which would overcomplicate
-// this project even more, but at some point this should be done.
+/// Runs the subcommand given in `args`.
+///
+/// # Errors
+///
+/// Returns an error if the configuration, database, or lockfile couldn't be opened or written, or if the subcommand
+/// itself fails.
+#[allow(clippy::too_many_lines)]
 pub fn run(args: Cli) -> Result<()> {
-    let Some(proj_dirs) = directories::ProjectDirs::from("com.github", "needlesslygrim", "Locket")
-    else {
-        bail!("Failed to get project directories")
-    };
+    // Alias it to `C` (Command)
+    use args::Subcommands as C;
 
+    // Generating a password doesn't touch the configuration or the database, so there's no reason to open them.
+    if let C::Generate(generate_args) = &args.subcommand {
+        let generated =
+            generator::generate(&generate_args.into()).wrap_err("Failed to generate a password")?;
+        println!("{}", generated.password.expose_secret());
+        eprintln!("Entropy: {:.1} bits", generated.entropy);
+        return Ok(());
+    }
+
+    let proj_dirs = vault::project_dirs()?;
     let conf_dir = proj_dirs.config_dir();
     let data_dir = proj_dirs.data_dir();
 
@@ -45,69 +79,178 @@ pub fn run(args: Cli) -> Result<()> {
         fs::create_dir_all(data_dir).wrap_err("Failed to create data dir")?;
     }
 
-    let conf_path = conf_dir.join(CONFIG_FILE_NAME);
-    let db_path = data_dir.join(DATABASE_FILE_NAME);
+    let conf_path = vault::config_path(&proj_dirs);
+    let db_path = vault::database_path(&proj_dirs);
+
+    let password = master_password(args.password_file.as_deref())
+        .wrap_err("Failed to get the master password")?;
 
-    // Alias it to `C` (Command)
-    use args::Subcommands as C;
     if let C::Init(InitArgs { port }) = args.subcommand {
         Config::init_interactive(&conf_path, &db_path, port)
             .wrap_err("Failed to initialise configuration file")?;
-        Database::init(&db_path).wrap_err("Failed to initialise database")?;
+        Database::init_interactive(&db_path, password.as_ref().map(SecretString::expose_secret))
+            .wrap_err("Failed to initialise database")?;
 
         println!("Successfully initialised a database and configuration file");
         return Ok(());
     }
 
-    let config =
-        Config::open_interactive(&conf_path).wrap_err("Failed to open config interactively")?;
-
-    let mut db = Database::open(&config.path).wrap_err("Failed to open the existing database")?;
+    // This is checked before anything else, so that nobody types in their password only to be told this.
+    if !conf_path
+        .try_exists()
+        .wrap_err("Failed to check whether the configuration file exists")?
+    {
+        eprintln!("You have not initialised Locket yet, please run `locket init` to initialise, then run this command again.");
+        return Ok(());
+    }
 
-    let mut lck_path = env::temp_dir();
-    lck_path.push(LCK_FILE_NAME);
+    if let C::Unlock(UnlockArgs { force }) = args.subcommand {
+        let config = Config::open(&conf_path).wrap_err("Failed to open the config")?;
+        return lock::unlock(&lock::path_for(&config.path), force)
+            .wrap_err("Failed to unlock the vault");
+    }
 
-    if let Err(err) = OpenOptions::new()
-        .create_new(true)
-        .write(true)
-        .open(&lck_path)
-    {
-        match err.kind() {
-            ErrorKind::AlreadyExists => {
-                eprintln!("An instance of Locket is already running, please kill it or wait for it to quit before trying to run another instance");
-                std::process::exit(1);
-            }
-            _ => bail!("Failed to open the lockfile: {}", err),
-        }
+    let password = match password {
+        Some(password) => password,
+        None => models::prompt_master_password()?,
+    };
+    let options = Options {
+        config_path: conf_path,
+        password,
+    };
+    let mut session = if matches!(args.subcommand, C::Migrate(_)) {
+        Locket::open_for_migration(options)?
+    } else {
+        Locket::open(options)?
     };
+    let db = &mut session.db;
+
+    // A dry run must leave the file exactly as it was, even though it has already been migrated in memory.
+    let should_sync = !matches!(
+        args.subcommand,
+        C::Migrate(MigrateArgs { dry_run: true }) | C::Import(ImportArgs { dry_run: true, .. })
+    );
+    let mut clear_clipboard_after = None;
 
     match args.subcommand {
         // Hopefully this isn't a bad idea :)
-        C::Init(_) => unsafe { unreachable_unchecked() },
-        C::New => db
-            .add_login_interactive()
+        C::Init(_) | C::Generate(_) | C::Unlock(_) => unsafe { unreachable_unchecked() },
+        C::New(args) => db
+            .add_login_with_args(args)
             .wrap_err("Failed to add a new login to the database")?,
-        C::Query(name) => db.query_interactive(name.name.as_deref()),
-        C::Remove => {
-            db.remove_interactive()
-                .wrap_err("Failed to remove a login from the database interactively")?;
+        C::Query(args) => db
+            .query_with_args(&args)
+            .wrap_err("Failed to print the logins")?,
+        C::Copy(args) => {
+            if db
+                .copy_interactive(&args, &session.config.clipboard)
+                .wrap_err("Failed to copy a login")?
+            {
+                clear_clipboard_after =
+                    Some(args.timeout.unwrap_or(session.config.clipboard_timeout))
+                        .filter(|timeout| *timeout > 0)
+                        .map(Duration::from_secs);
+            }
         }
-        #[cfg(feature = "web")]
-        C::Serve => {
-            net::serve(&mut db, config.port, &lck_path).wrap_err("Failed to serve webpage")?;
+        C::Match(args) => db
+            .match_interactive(&args.url)
+            .wrap_err("Failed to find logins for the URL")?,
+        C::Otp(name) => db
+            .otp_interactive(name.name.as_deref())
+            .wrap_err("Failed to get a one-time password")?,
+        C::History(args) => db
+            .history_interactive(args.name.as_deref(), args.restore, args.reveal)
+            .wrap_err("Failed to get the password history")?,
+        C::Edit => db
+            .edit_interactive()
+            .wrap_err("Failed to edit a login interactively")?,
+        C::Remove(args) => {
+            db.remove_with_args(&args)
+                .wrap_err("Failed to remove a login from the database")?;
         }
-    };
-
-    db.sync().wrap_err("Failed to sync database to disk")?;
-    if let Err(err) = fs::remove_file(lck_path) {
-        match err.kind() {
-            ErrorKind::NotFound => {
-                // TODO: Improve this message.
-                eprintln!("Tried to remove the lockfile, but it was already gone");
-                std::process::exit(1);
+        C::Import(args) => db
+            .import_with_args(&args)
+            .wrap_err("Failed to import logins")?,
+        C::Export(args) => db
+            .export_with_args(&args)
+            .wrap_err("Failed to export the database")?,
+        C::Audit(args) => db
+            .audit_with_args(&args)
+            .wrap_err("Failed to audit the database")?,
+        C::BreachCheck(args) => db
+            .breach_check_with_args(&args)
+            .wrap_err("Failed to check for breached passwords")?,
+        C::Migrate(MigrateArgs { dry_run }) => {
+            if db.migrations.is_empty() {
+                println!(
+                    "The database is already at the newest format version ({})",
+                    migrations::CURRENT_VERSION
+                );
+            } else {
+                println!(
+                    "{} the following migrations:",
+                    if dry_run { "Would run" } else { "Ran" }
+                );
+                for migration in &db.migrations {
+                    println!("  {migration}");
+                }
             }
-            _ => bail!("Failed to remove the lockfile: {}", err),
         }
-    };
+        #[cfg(feature = "web")]
+        C::Serve(args) if args.set_passphrase => session
+            .config
+            .set_web_passphrase_with_args(&session.config_path, &args)
+            .wrap_err("Failed to set the passphrase for the web interface")?,
+        #[cfg(feature = "web")]
+        C::Serve(args) => {
+            session.config.apply_serve_args(&args);
+            // The flags still take priority over the config when it's reloaded.
+            let conf_path = session.config_path.clone();
+            let reload = || {
+                let mut config = Config::open(&conf_path)?;
+                config.apply_serve_args(&args);
+                Ok(config)
+            };
+            net::serve(&mut session, data_dir, reload).wrap_err("Failed to serve webpage")?;
+        }
+    }
+
+    // Clearing the clipboard waits until the vault has been closed, so that the lock isn't held in the meantime.
+    let clipboard = std::mem::take(&mut session.config.clipboard);
+    if should_sync {
+        session.close()?;
+    } else {
+        drop(session);
+    }
+
+    if let Some(timeout) = clear_clipboard_after {
+        eprintln!(
+            "Clearing the clipboard in {}s, press Ctrl-C to keep it",
+            timeout.as_secs()
+        );
+        thread::sleep(timeout);
+        clipboard
+            .clear()
+            .wrap_err("Failed to clear the clipboard")?;
+    }
     Ok(())
 }
+
+// Gets the master password without prompting, either from the file passed with `--password-file`, or from the
+// `LOCKET_PASSWORD` environment variable. If neither is present, `None` is returned and we prompt for it later.
+fn master_password(password_file: Option<&Path>) -> Result<Option<SecretString>> {
+    if let Some(path) = password_file {
+        let password = SecretString::new(
+            fs::read_to_string(path).wrap_err("Failed to read the master password file")?,
+        );
+        // Editors like to add a trailing newline, which almost certainly isn't part of the password.
+        return Ok(Some(SecretString::new(
+            password
+                .expose_secret()
+                .trim_end_matches(['\r', '\n'])
+                .to_owned(),
+        )));
+    }
+
+    Ok(env::var(PASSWORD_ENV_VAR).ok().map(SecretString::new))
+}
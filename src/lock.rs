@@ -0,0 +1,189 @@
+use std::{
+    fmt::Display,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+
+static EXTENSION: &str = "lck";
+
+// Stops two instances of Locket from using the same vault at once, and overwriting each other's changes. It's an
+// advisory lock (`flock()` on Unix) on a file next to the database, which the OS lets go of when the process exits,
+// however it exits, so a crash can't leave the vault locked. The file holds the PID and hostname of whoever has the
+// lock, so that anybody else can be told who it is.
+pub struct Lock {
+    file: File,
+}
+
+// Every vault has its own lock, which sits next to its database.
+pub fn path_for(db_path: &Path) -> PathBuf {
+    db_path.with_extension(EXTENSION)
+}
+
+// Whoever holds the lock, or last held it.
+struct Holder {
+    pid: u32,
+    hostname: String,
+}
+
+impl Lock {
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .wrap_err_with(|| format!("Failed to open the lockfile at {}", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = Holder::read(&mut file);
+                let description = describe(holder.as_ref());
+                if holder.and_then(|holder| holder.is_running()) == Some(false) {
+                    bail!("{description}, run `locket unlock --force` if nothing else is using it");
+                }
+                bail!("{description}, please kill it or wait for it to quit before trying again");
+            }
+            Err(TryLockError::Error(err)) => {
+                return Err(err).wrap_err("Failed to lock the lockfile")
+            }
+        }
+
+        // The file is emptied whenever the lock is let go of properly, so anything still in it was left by a crash.
+        if let Some(holder) = Holder::read(&mut file) {
+            eprintln!("Took over a stale lock left behind by {holder}");
+        }
+        file.set_len(0)
+            .and_then(|()| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| writeln!(file, "{}\n{}", process::id(), hostname()))
+            .wrap_err("Failed to write to the lockfile")?;
+
+        Ok(Self { file })
+    }
+}
+
+impl Drop for Lock {
+    // There's nothing to be done if this fails, and closing the file releases the lock either way.
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+    }
+}
+
+// Gets rid of a lock which was left behind, for `locket unlock`. A lock that some process is actually holding is only
+// removed with `force`, in which case that process keeps its lock on a file which nothing else can open anymore.
+pub fn unlock(path: &Path, force: bool) -> Result<()> {
+    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            println!("The vault isn't locked");
+            return Ok(());
+        }
+        Err(err) => {
+            return Err(err)
+                .wrap_err_with(|| format!("Failed to open the lockfile at {}", path.display()))
+        }
+    };
+
+    match file.try_lock() {
+        Ok(()) => {
+            if let Some(holder) = Holder::read(&mut file) {
+                file.set_len(0).wrap_err("Failed to empty the lockfile")?;
+                println!("Removed a stale lock left behind by {holder}");
+            } else {
+                println!("The vault isn't locked");
+            }
+        }
+        Err(TryLockError::WouldBlock) => {
+            let holder = Holder::read(&mut file);
+            let description = describe(holder.as_ref());
+            if !force {
+                bail!("{description}, pass `--force` to remove the lock anyway");
+            }
+
+            drop(file);
+            fs::remove_file(path).wrap_err("Failed to remove the lockfile")?;
+            println!("Removed the lock");
+            if holder.and_then(|holder| holder.is_running()) != Some(false) {
+                eprintln!("If it's still running, it may overwrite any changes made to the vault from now on");
+            }
+        }
+        Err(TryLockError::Error(err)) => return Err(err).wrap_err("Failed to lock the lockfile"),
+    }
+
+    Ok(())
+}
+
+fn describe(holder: Option<&Holder>) -> String {
+    match holder {
+        Some(holder) if holder.is_running() == Some(false) => {
+            format!("The vault is locked by {holder}, which isn't running anymore")
+        }
+        Some(holder) => format!("The vault is being used by {holder}"),
+        None => String::from("The vault is being used by another instance of Locket"),
+    }
+}
+
+impl Holder {
+    // Anything that can't be read, or doesn't make sense, is treated as nobody.
+    fn read(file: &mut File) -> Option<Self> {
+        let mut contents = String::new();
+        file.seek(SeekFrom::Start(0)).ok()?;
+        file.read_to_string(&mut contents).ok()?;
+
+        let mut lines = contents.lines();
+        let pid = lines.next()?.trim().parse().ok()?;
+        let hostname = lines.next()?.trim().to_owned();
+        Some(Self { pid, hostname })
+    }
+
+    // Whether the process is still around, which can only be known if it's on this machine.
+    fn is_running(&self) -> Option<bool> {
+        if self.hostname == hostname() {
+            is_running(self.pid)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for Holder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PID {} on {}", self.pid, self.hostname)
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return String::from("unknown");
+    }
+    let len = buf.iter().position(|byte| *byte == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| String::from("unknown"))
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> Option<bool> {
+    let pid = libc::pid_t::try_from(pid).ok()?;
+    // Signal 0 isn't actually sent, it only checks whether the process exists. `EPERM` means that it does, but that
+    // it belongs to somebody else.
+    Some(
+        unsafe { libc::kill(pid, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM),
+    )
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> Option<bool> {
+    None
+}
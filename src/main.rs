@@ -4,9 +4,6 @@ use clap::Parser;
 use color_eyre::eyre::{eyre, Context};
 use log::error;
 
-// TODO: Find a way to exit properly, instead of using `std::process::exit()`.
-// This might give us a way to run destructors automagically. However, it may
-// not work with color_eyre, so research is needed :^).
 fn main() -> color_eyre::Result<()> {
     let args = locket::args::Cli::parse();
     color_eyre::install()?;
@@ -0,0 +1,147 @@
+use std::{fmt::Display, mem};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use rmpv::Value;
+
+use crate::errors::LocketError;
+
+// Every database file written since versioning was added starts with these bytes, followed by the format version as a
+// little-endian `u16`. Files without them are treated as version 0.
+pub const MAGIC: &[u8; 8] = b"LOCKETDB";
+pub const CURRENT_VERSION: u16 = 2;
+// Before this version, only the salt was authenticated along with the encrypted contents, not the whole header.
+pub const AUTHENTICATED_HEADER_VERSION: u16 = 2;
+
+// A single step in the chain of migrations. Each step upgrades the decrypted contents of a database from `from` to
+// `from + 1`, and returns the number of logins it changed, which is only used for reporting.
+pub struct Migration {
+    pub from: u16,
+    pub description: &'static str,
+    apply: fn(&mut Value) -> Result<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AppliedMigration {
+    pub from: u16,
+    pub to: u16,
+    pub description: &'static str,
+    pub changed: usize,
+}
+
+// Must be kept in order, with no gaps, ending at `CURRENT_VERSION - 1`.
+static MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "Store the database and logins as maps of named fields, instead of arrays",
+        apply: named_fields,
+    },
+    Migration {
+        from: 1,
+        description: "Authenticate the whole header of the file when encrypting it, rather than only the salt",
+        apply: authenticated_header,
+    },
+];
+
+pub fn split_header(buf: &[u8]) -> (u16, &[u8]) {
+    match buf.strip_prefix(MAGIC.as_slice()) {
+        Some([lo, hi, body @ ..]) => (u16::from_le_bytes([*lo, *hi]), body),
+        _ => (0, buf),
+    }
+}
+
+pub fn header() -> Vec<u8> {
+    let mut header = Vec::with_capacity(MAGIC.len() + 2);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&CURRENT_VERSION.to_le_bytes());
+    header
+}
+
+// A newer version of Locket might have changed anything after the header, so nothing else should be read from such a
+// file before this has been checked.
+pub fn ensure_supported(version: u16) -> Result<()> {
+    if version > CURRENT_VERSION {
+        bail!(LocketError::UnsupportedVersionError);
+    }
+    Ok(())
+}
+
+// Runs every migration needed to bring `value` from `version` up to `CURRENT_VERSION`.
+pub fn migrate(version: u16, value: &mut Value) -> Result<Vec<AppliedMigration>> {
+    ensure_supported(version)?;
+
+    MIGRATIONS
+        .iter()
+        .skip_while(|migration| migration.from < version)
+        .map(|migration| {
+            let changed = (migration.apply)(value).map_err(|e| {
+                eyre!(
+                    "Failed to migrate the database from version {} to {}: {e}",
+                    migration.from,
+                    migration.from + 1
+                )
+            })?;
+
+            Ok(AppliedMigration {
+                from: migration.from,
+                to: migration.from + 1,
+                description: migration.description,
+                changed,
+            })
+        })
+        .collect()
+}
+
+impl Display for AppliedMigration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {}: {} ({} login(s) affected)",
+            self.from, self.to, self.description, self.changed
+        )
+    }
+}
+
+// Version 0 -> 1. `rmp_serde::encode::to_vec()` writes structs as arrays, which means that adding, removing or
+// reordering a field breaks every existing database. From version 1 onwards, structs are written as maps instead.
+fn named_fields(value: &mut Value) -> Result<usize> {
+    let mut db = fields_to_map(mem::replace(value, Value::Nil), &["logins"])?;
+    let Some((_, Value::Map(logins))) = db.first_mut() else {
+        bail!("Expected the logins to be a map");
+    };
+
+    for (_, login) in logins.iter_mut() {
+        *login = Value::Map(fields_to_map(
+            mem::replace(login, Value::Nil),
+            &["name", "username", "password"],
+        )?);
+    }
+    let changed = logins.len();
+
+    *value = Value::Map(db);
+    Ok(changed)
+}
+
+// Version 1 -> 2. Nothing in the contents changes, the header is authenticated as soon as the database is written.
+#[allow(clippy::unnecessary_wraps)]
+fn authenticated_header(_value: &mut Value) -> Result<usize> {
+    Ok(0)
+}
+
+fn fields_to_map(value: Value, names: &[&str]) -> Result<Vec<(Value, Value)>> {
+    let Value::Array(fields) = value else {
+        bail!("Expected an array of {} fields", names.len());
+    };
+    if fields.len() != names.len() {
+        bail!(
+            "Expected an array of {} fields, found {}",
+            names.len(),
+            fields.len()
+        );
+    }
+
+    Ok(names
+        .iter()
+        .map(|name| Value::from(*name))
+        .zip(fields)
+        .collect())
+}
@@ -1,16 +1,20 @@
 use std::io::ErrorKind;
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap, HashSet},
     fmt::Display,
     fs,
     fs::{File, OpenOptions},
-    io::{prelude::*, BufReader, BufWriter},
+    io::{prelude::*, BufReader, BufWriter, IsTerminal},
     path::{Path, PathBuf},
 };
 
+#[cfg(feature = "web")]
+use std::net::{IpAddr, Ipv4Addr};
+
+use chrono::{DateTime, NaiveDate, Utc};
 use color_eyre::eyre::{bail, Context, Result};
 use dialoguer::theme::ColorfulTheme;
-use dialoguer::{FuzzySelect, Input, Password};
+use dialoguer::{Confirm, FuzzySelect, Input, Password, Select};
 use itertools::Itertools;
 use serde_derive::{Deserialize, Serialize};
 use tabled::{
@@ -19,14 +23,84 @@ use tabled::{
     Table, Tabled,
 };
 use uuid::Uuid;
+use zeroize::Zeroizing;
 
+#[cfg(feature = "web")]
+use crate::args::ServeArgs;
+use crate::args::{
+    AuditArgs, BreachCheckArgs, CopyArgs, ExportArgs, ExportFormat, Format, ImportArgs, NewArgs,
+    QueryArgs, RemoveArgs, ReportFormat, Source,
+};
+use crate::audit;
+use crate::breach;
+use crate::clipboard;
+#[cfg(feature = "web")]
+use crate::crypto;
+use crate::crypto::{Cipher, Envelope};
+use crate::domain;
 use crate::errors::LocketError;
+use crate::export;
+use crate::generator::{self, Spec};
+use crate::import;
+use crate::migrations::{self, AppliedMigration};
+use crate::otp::{Otp, OtpCode};
+use crate::output;
+use crate::secret::SecretString;
+
+static BACKUP_SUFFIX: &str = ".bak";
+static TEMP_SUFFIX: &str = ".tmp";
+const DEFAULT_HISTORY_LIMIT: usize = 10;
+const DEFAULT_CLIPBOARD_TIMEOUT: u64 = 30;
+#[cfg(feature = "web")]
+const DEFAULT_SESSION_TIMEOUT: u64 = 15 * 60;
+static MASTER_PASSWORD_HINT: &str =
+    "Set `LOCKET_PASSWORD` or pass `--password-file` to give the master password without a prompt";
 
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub path: PathBuf,
     #[cfg(feature = "web")]
     pub port: u16,
+    // The address that `locket serve` listens on, e.g. `::1` for IPv6, or a LAN address.
+    #[cfg(feature = "web")]
+    #[serde(default = "default_bind")]
+    pub bind: IpAddr,
+    // Listening on a Unix socket instead means that only we can connect, so other users can't even reach the login
+    // page.
+    #[cfg(feature = "web")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket_path: Option<PathBuf>,
+    // Any other names (`host` or `host:port`) that the web interface is reached by, e.g. when it's behind a reverse
+    // proxy. Requests for anything else are rejected. A port of 80 or 443 can be left out, as browsers do. When
+    // `bind` is `0.0.0.0` or `::`, only `localhost` works until the names it's actually reached by are added here.
+    #[cfg(feature = "web")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_hosts: Vec<String>,
+    // How many previous passwords to keep for each login.
+    #[serde(default = "default_history_limit")]
+    pub history_limit: usize,
+    // How many seconds to wait before clearing the clipboard after `locket copy`. 0 leaves it alone.
+    #[serde(default = "default_clipboard_timeout")]
+    pub clipboard_timeout: u64,
+    #[serde(default)]
+    pub clipboard: clipboard::Backend,
+    // An Argon2 hash of the passphrase for the web interface, which is set with `locket serve --set-passphrase`.
+    // Without one, the web interface asks for the master password instead.
+    #[cfg(feature = "web")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub web_passphrase: Option<String>,
+    // How many seconds a web session can go unused before having to log in again.
+    #[cfg(feature = "web")]
+    #[serde(default = "default_session_timeout")]
+    pub session_timeout: u64,
+    // The certificate and private key (both PEM) for the web interface. Without them, a self-signed certificate is
+    // made and kept in the data directory.
+    #[cfg(feature = "web")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<PathBuf>,
+    #[cfg(feature = "web")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<PathBuf>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -34,13 +108,81 @@ pub struct Database {
     pub logins: HashMap<Uuid, Login>,
     #[serde(skip)]
     pub path: PathBuf,
+    #[serde(skip)]
+    cipher: Option<Cipher>,
+    // The migrations which were run when this database was opened. They won't have been written to disk until the
+    // next `sync()`.
+    #[serde(skip)]
+    pub migrations: Vec<AppliedMigration>,
+    // Set when the main file couldn't be read and the backup was opened instead, in which case the next `sync()`
+    // mustn't replace the (good) backup with the (bad) main file. Once that sync has worked, the main file is good
+    // again.
+    #[serde(skip)]
+    restored_from_backup: bool,
+    // Copied from the config, as it's needed whenever a password is replaced.
+    #[serde(skip)]
+    pub history_limit: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize, Tabled)]
 pub struct Login {
     pub name: String,
     pub username: String,
-    pub password: String,
+    pub password: SecretString,
+    #[serde(default)]
+    #[tabled(display_with = "display_urls")]
+    pub urls: Vec<String>,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    #[tabled(display_with = "display_tags")]
+    pub tags: BTreeSet<String>,
+    #[serde(default)]
+    #[tabled(display_with = "display_fields")]
+    pub fields: Vec<CustomField>,
+    #[serde(default)]
+    #[tabled(skip)]
+    pub otp: Option<Otp>,
+    // The newest password is first.
+    #[serde(default)]
+    #[tabled(skip)]
+    pub history: Vec<PreviousPassword>,
+}
+
+// A partial update to a login, where only the fields which are present are changed.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoginPatch {
+    pub name: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<SecretString>,
+    pub urls: Option<Vec<String>>,
+    pub notes: Option<String>,
+    pub tags: Option<BTreeSet<String>>,
+    pub fields: Option<Vec<CustomField>>,
+}
+
+// A password which has been replaced, which we keep around in case the new one never made it to the website.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PreviousPassword {
+    pub password: SecretString,
+    pub replaced: DateTime<Utc>,
+}
+
+// Anything else that needs to be stored alongside a login, e.g. security questions or account numbers.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CustomField {
+    pub name: String,
+    pub value: FieldValue,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum FieldValue {
+    Text(String),
+    Hidden(SecretString),
+    Url(String),
+    Date(NaiveDate),
 }
 
 impl Config {
@@ -70,6 +212,23 @@ impl Config {
                 path: PathBuf::from(db_path),
                 #[cfg(feature = "web")]
                 port,
+                #[cfg(feature = "web")]
+                bind: default_bind(),
+                #[cfg(feature = "web")]
+                socket_path: None,
+                #[cfg(feature = "web")]
+                allowed_hosts: Vec::new(),
+                history_limit: DEFAULT_HISTORY_LIMIT,
+                clipboard_timeout: DEFAULT_CLIPBOARD_TIMEOUT,
+                clipboard: clipboard::Backend::default(),
+                #[cfg(feature = "web")]
+                web_passphrase: None,
+                #[cfg(feature = "web")]
+                session_timeout: DEFAULT_SESSION_TIMEOUT,
+                #[cfg(feature = "web")]
+                tls_cert: None,
+                #[cfg(feature = "web")]
+                tls_key: None,
             };
             Self::init(path, &config).wrap_err(
                 "Failed to initialise configuration file after interactively getting config",
@@ -78,6 +237,7 @@ impl Config {
             return Ok(config);
         }
 
+        ensure_terminal()?;
         let theme = ColorfulTheme::default();
 
         #[cfg(feature = "web")]
@@ -99,6 +259,23 @@ impl Config {
             path: PathBuf::from(db_path),
             #[cfg(feature = "web")]
             port,
+            #[cfg(feature = "web")]
+            bind: default_bind(),
+            #[cfg(feature = "web")]
+            socket_path: None,
+            #[cfg(feature = "web")]
+            allowed_hosts: Vec::new(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            clipboard_timeout: DEFAULT_CLIPBOARD_TIMEOUT,
+            clipboard: clipboard::Backend::default(),
+            #[cfg(feature = "web")]
+            web_passphrase: None,
+            #[cfg(feature = "web")]
+            session_timeout: DEFAULT_SESSION_TIMEOUT,
+            #[cfg(feature = "web")]
+            tls_cert: None,
+            #[cfg(feature = "web")]
+            tls_key: None,
         };
 
         Self::init(path, &config).wrap_err(
@@ -108,7 +285,14 @@ impl Config {
         Ok(config)
     }
 
-    fn open(path: &Path) -> Result<Self> {
+    pub fn open(path: &Path) -> Result<Self> {
+        if !path
+            .try_exists()
+            .wrap_err("Failed to check whether the configuration file exists")?
+        {
+            bail!(LocketError::NotInitialisedError);
+        }
+
         let f = File::open(path).wrap_err("Failed to open file handle to configuration file")?;
         let mut reader = BufReader::new(f);
         let mut buf = String::with_capacity(
@@ -126,24 +310,81 @@ impl Config {
         toml::de::from_str(&buf).wrap_err("Failed to parse configuration file")
     }
 
-    pub(crate) fn open_interactive(path: &Path) -> Result<Self> {
-        if !path
-            .try_exists()
-            .wrap_err("Failed to check whether the database exists")?
-        {
-            eprintln!("You have not initialised Locket yet, please run `locket init` to initialise, then run this command again.");
-            std::process::exit(0);
+    // The config holds the hash of the web passphrase, so only we can read it. It's written to a new file which is
+    // then moved over the old one, since the old one might have been readable by anybody.
+    #[cfg(feature = "web")]
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let buf =
+            toml::ser::to_string_pretty(self).wrap_err("Failed to serialise configuration file")?;
+
+        let tmp_path = sibling_path(path, TEMP_SUFFIX);
+        if let Err(err) = fs::remove_file(&tmp_path) {
+            if err.kind() != ErrorKind::NotFound {
+                return Err(err).wrap_err("Failed to remove an old temporary configuration file");
+            }
         }
+        create_private_file(&tmp_path)
+            .and_then(|mut f| f.write_all(buf.as_bytes()).and_then(|()| f.sync_all()))
+            .wrap_err("Failed to write configuration file")?;
+        fs::rename(&tmp_path, path).wrap_err("Failed to replace the configuration file")
+    }
 
-        Self::open(path).wrap_err("Failed to load configuration from disk")
+    // Flags given to `locket serve` take precedence over the config. A socket and an address can't both be listened
+    // on, so giving one replaces the other.
+    #[cfg(feature = "web")]
+    pub(crate) fn apply_serve_args(&mut self, args: &ServeArgs) {
+        if args.bind.is_some() || args.port.is_some() {
+            self.socket_path = None;
+        }
+        if let Some(bind) = args.bind {
+            self.bind = bind;
+        }
+        if let Some(port) = args.port {
+            self.port = port;
+        }
+        if let Some(socket_path) = &args.socket_path {
+            self.socket_path = Some(socket_path.clone());
+        }
+    }
+
+    #[cfg(feature = "web")]
+    pub(crate) fn set_web_passphrase_with_args(
+        &mut self,
+        path: &Path,
+        args: &ServeArgs,
+    ) -> Result<()> {
+        let passphrase = if args.passphrase_stdin {
+            read_stdin_secret()?
+        } else {
+            ensure_terminal()?;
+            SecretString::new(
+                Password::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Enter the new passphrase for the web interface")
+                    .with_confirmation("Confirm the passphrase", "The passphrases don't match")
+                    .interact()
+                    .wrap_err("Failed to read the passphrase from console")?,
+            )
+        };
+        if passphrase.expose_secret().is_empty() {
+            bail!("The passphrase can't be empty");
+        }
+
+        self.web_passphrase = Some(
+            crypto::hash_password(passphrase.expose_secret())
+                .wrap_err("Failed to hash the passphrase")?,
+        );
+        self.save(path)?;
+        println!(
+            "The web interface will now ask for this passphrase, instead of the master password"
+        );
+        Ok(())
     }
 }
 
 impl Database {
-    pub fn init(path: &Path) -> Result<Self> {
-        // Discard the file descriptor because we don't need to actually write to the file on
-        // initialisation, we only need to create the file. Ideally there would be an
-        // `fs::create_file()`, but there is not.
+    pub fn init(path: &Path, password: &str) -> Result<Self> {
+        // Discard the file descriptor, `sync()` opens the file again itself. We only do this to make sure that we never
+        // overwrite an existing database.
         if let Err(err) = OpenOptions::new()
             .read(true)
             .write(true)
@@ -158,41 +399,205 @@ impl Database {
             };
         }
 
-        Ok(Self {
-            logins: HashMap::new(),
+        let mut db = Self {
             path: PathBuf::from(path),
-        })
+            cipher: Some(Cipher::new(password).wrap_err("Failed to derive the database key")?),
+            ..Default::default()
+        };
+        db.sync()
+            .wrap_err("Failed to write the new database to disk")?;
+
+        Ok(db)
     }
 
-    pub fn open(path: &Path) -> Result<Self> {
-        let reader =
+    pub(crate) fn init_interactive(path: &Path, password: Option<&str>) -> Result<Self> {
+        if let Some(password) = password {
+            return Self::init(path, password);
+        }
+
+        ensure_terminal().wrap_err(MASTER_PASSWORD_HINT)?;
+        let password = SecretString::new(
+            Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("Enter a master password for the database")
+                .with_confirmation("Confirm the master password", "The passwords don't match")
+                .interact()
+                .wrap_err("Failed to read the master password from console")?,
+        );
+
+        Self::init(path, password.expose_secret())
+    }
+
+    pub fn open(path: &Path, password: &str) -> Result<Self> {
+        Self::open_with(path, password, false)
+    }
+
+    // Like `open`, except that a database from before encryption was added is opened too, so that `locket migrate` can
+    // encrypt it with `password`. Anything else refuses to open one, otherwise any password at all would be accepted.
+    pub(crate) fn open_for_migration(path: &Path, password: &str) -> Result<Self> {
+        Self::open_with(path, password, true)
+    }
+
+    fn open_with(path: &Path, password: &str, allow_unencrypted: bool) -> Result<Self> {
+        let err = match Self::decode(path, password, allow_unencrypted) {
+            Ok(db) => return Ok(db),
+            Err(err) => err,
+        };
+
+        // Neither of these would be fixed by opening the backup instead.
+        if matches!(
+            err.downcast_ref::<LocketError>(),
+            Some(
+                LocketError::WrongPasswordError
+                    | LocketError::UnsupportedVersionError
+                    | LocketError::UnencryptedDatabaseError
+            )
+        ) {
+            return Err(err);
+        }
+
+        let bak_path = sibling_path(path, BACKUP_SUFFIX);
+        if !has_backup(path) {
+            return Err(err);
+        }
+
+        let mut db = Self::decode(&bak_path, password, allow_unencrypted).wrap_err_with(|| {
+            format!("Failed to open the database ({err:#}), and failed to open the backup")
+        })?;
+        eprintln!(
+            "Failed to open the database ({err:#}), the backup at {} was opened instead",
+            bak_path.display()
+        );
+        db.path = PathBuf::from(path);
+        db.restored_from_backup = true;
+
+        Ok(db)
+    }
+
+    fn decode(path: &Path, password: &str, allow_unencrypted: bool) -> Result<Self> {
+        let mut reader =
             BufReader::new(File::open(path).wrap_err("Failed to open file handle to database")?);
-        let is_empty = match fs::metadata(path) {
-            Ok(meta) => meta.len(),
-            Err(err) => match err.kind() {
-                ErrorKind::NotFound => 0,
-                _ => Err(err).wrap_err("Failed to get metadata of configuration file")?,
-            },
-        } == 0;
+        let mut buf = Vec::new();
+        reader
+            .read_to_end(&mut buf)
+            .wrap_err("Failed to read the database from disk")?;
 
-        let mut db = if is_empty {
-            Self::default()
+        // `init` writes the database straight away, so an empty file without a backup can only be one whose `init` was
+        // interrupted. With a backup, it's a write that was interrupted, and the backup has to be opened instead.
+        if buf.is_empty() {
+            if has_backup(path) {
+                bail!(LocketError::CorruptDatabaseError);
+            }
+            return Ok(Self {
+                path: PathBuf::from(path),
+                cipher: Some(Cipher::new(password).wrap_err("Failed to derive the database key")?),
+                ..Default::default()
+            });
+        }
+
+        let mut db = Self::decode_bytes(&buf, password, allow_unencrypted)?;
+        db.path = PathBuf::from(path);
+        Ok(db)
+    }
+
+    // Exports are always encrypted, and have no backup to fall back on, so unlike `open`, nothing else is accepted.
+    pub(crate) fn decode_export(buf: &[u8], password: &str) -> Result<Self> {
+        let (version, body) = migrations::split_header(buf);
+        migrations::ensure_supported(version)?;
+        if rmp_serde::decode::from_slice::<Envelope>(body).is_err() {
+            bail!("The file isn't an encrypted export from Locket");
+        }
+        Self::decode_bytes(buf, password, false)
+    }
+
+    fn decode_bytes(buf: &[u8], password: &str, allow_unencrypted: bool) -> Result<Self> {
+        let (version, body) = migrations::split_header(buf);
+        migrations::ensure_supported(version)?;
+        let header = &buf[..buf.len() - body.len()];
+        let envelope = rmp_serde::decode::from_slice::<Envelope>(body).ok();
+        let (cipher, plaintext) = match &envelope {
+            Some(envelope) => Cipher::open(
+                envelope,
+                password,
+                (version >= migrations::AUTHENTICATED_HEADER_VERSION).then_some(header),
+            )?,
+            // Databases created before encryption was added are plain MessagePack. Migrating is the only time we
+            // open them, and they're then encrypted with the given password when they're synced.
+            None if version == 0 && !allow_unencrypted => {
+                bail!(LocketError::UnencryptedDatabaseError)
+            }
+            None if version == 0 => (
+                Cipher::new(password).wrap_err("Failed to derive the database key")?,
+                Zeroizing::new(body.to_vec()),
+            ),
+            None => bail!(LocketError::CorruptDatabaseError),
+        };
+
+        // The migrations work on a generic MessagePack value, which is then written back out so that the database
+        // itself is always decoded by `rmp_serde`, the same way it was encoded. `rmpv`'s own deserialiser doesn't
+        // understand how `rmp_serde` represents enums.
+        let (plaintext, applied) = if version == migrations::CURRENT_VERSION {
+            (plaintext, Vec::new())
         } else {
-            rmp_serde::decode::from_read(reader).wrap_err("Failed to parse database contents")?
+            let mut value = rmpv::decode::read_value(&mut plaintext.as_slice())
+                .map_err(|_| LocketError::CorruptDatabaseError)?;
+            let applied = migrations::migrate(version, &mut value)?;
+
+            let mut migrated = Zeroizing::new(Vec::new());
+            rmpv::encode::write_value(&mut *migrated, &value)
+                .wrap_err("Failed to re-encode the migrated database")?;
+            (migrated, applied)
         };
-        db.path = PathBuf::from(path);
+
+        let mut db: Self = rmp_serde::decode::from_slice(&plaintext)
+            .wrap_err("Failed to parse database contents")?;
+        if envelope.is_none() {
+            eprintln!("The database is not encrypted, it will be encrypted with the master password when it is next saved");
+        }
+        db.cipher = Some(cipher);
+        db.migrations = applied;
 
         Ok(db)
     }
 
-    pub fn add_login(&mut self, login: Login) {
+    pub fn add_login(&mut self, login: Login) -> Uuid {
         let id = Uuid::new_v4();
         // TODO: However unlikely it is that there will be a collision, do proper things here.
         let old_val = self.logins.insert(id, login);
         assert!(old_val.is_none());
+        id
+    }
+
+    // Adds a login from the flags given to `locket new`, or asks for everything if there's no `--name`.
+    pub(crate) fn add_login_with_args(&mut self, args: NewArgs) -> Result<()> {
+        let Some(name) = args.name else {
+            return self.add_login_interactive();
+        };
+
+        let password = if args.password_stdin {
+            read_stdin_secret().wrap_err("Failed to read the password from stdin")?
+        } else {
+            generator::generate(&Spec::default())
+                .wrap_err("Failed to generate a password")?
+                .password
+        };
+
+        let mut new_login = Login::new(name, args.username.unwrap_or_default(), password);
+        new_login.urls = args.urls;
+        new_login.tags = args.tags.into_iter().collect();
+        new_login.notes = args.notes.unwrap_or_default();
+        new_login
+            .normalise_urls()
+            .wrap_err("Failed to parse websites")?;
+        if let Some(uri) = args.otp_uri {
+            new_login.otp =
+                Some(Otp::from_uri(&uri).wrap_err("Failed to parse one-time password URI")?);
+        }
+        self.add_login(new_login);
+        Ok(())
     }
 
     pub(crate) fn add_login_interactive(&mut self) -> Result<()> {
+        ensure_terminal()?;
         let theme = ColorfulTheme::default();
 
         let name = Input::<String>::with_theme(&theme)
@@ -207,13 +612,33 @@ impl Database {
             .interact_text()
             .wrap_err("Failed to read username from console")?;
 
-        let password = Password::with_theme(&theme)
-            .with_prompt("Enter the password for this login")
-            .allow_empty_password(true)
-            .interact()
-            .wrap_err("Failed to read password from console")?;
+        let password = read_password_interactive(&theme)?;
+        let urls = read_urls_interactive(&theme, "")?;
+
+        let otp_uri = Input::<String>::with_theme(&theme)
+            .with_prompt("Enter an otpauth:// URI for one-time passwords (leave empty for none)")
+            .allow_empty(true)
+            .validate_with(|uri: &String| {
+                if uri.is_empty() {
+                    return Ok(());
+                }
+                Otp::from_uri(uri).map(|_| ()).map_err(|e| e.to_string())
+            })
+            .interact_text()
+            .wrap_err("Failed to read one-time password URI from console")?;
 
-        let new_login = Login::new(name, username, password);
+        let mut new_login = Login::new(name, username, password);
+        new_login.urls = urls;
+        new_login
+            .read_details_interactive(&theme)
+            .wrap_err("Failed to read the details of the login from console")?;
+        new_login
+            .normalise_urls()
+            .wrap_err("Failed to parse websites")?;
+        if !otp_uri.is_empty() {
+            new_login.otp =
+                Some(Otp::from_uri(&otp_uri).wrap_err("Failed to parse one-time password URI")?);
+        }
         self.add_login(new_login);
         Ok(())
     }
@@ -224,6 +649,200 @@ impl Database {
         }
     }
 
+    // Imports the logins from an export, leaving out any which are already in the database.
+    pub(crate) fn import_with_args(&mut self, args: &ImportArgs) -> Result<()> {
+        let password = if matches!(args.from, Source::Locket) {
+            Some(if args.password_stdin {
+                read_stdin_secret().wrap_err("Failed to read the password from stdin")?
+            } else {
+                ensure_terminal()?;
+                SecretString::new(
+                    Password::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Enter the password of the export")
+                        .interact()
+                        .wrap_err("Failed to read the password from console")?,
+                )
+            })
+        } else {
+            None
+        };
+        let entries = import::read(
+            args.from,
+            &args.file,
+            password.as_ref().map(SecretString::expose_secret),
+        )
+        .wrap_err_with(|| format!("Failed to read {}", args.file.display()))?;
+
+        // Logins are considered to be the same if all of these match.
+        let mut seen: HashSet<(String, String, String)> = self
+            .logins
+            .values()
+            .map(|login| {
+                (
+                    login.name.clone(),
+                    login.username.clone(),
+                    login.password.expose_secret().to_owned(),
+                )
+            })
+            .collect();
+
+        let mut logins = Vec::new();
+        let mut skipped = Vec::new();
+        let mut duplicates = Vec::new();
+        let mut warnings = Vec::new();
+        for entry in entries {
+            warnings.extend(
+                entry
+                    .warnings
+                    .into_iter()
+                    .map(|warning| format!("{}: {warning}", entry.location)),
+            );
+            let login = match entry.login {
+                Ok(login) => login,
+                Err(e) => {
+                    skipped.push(format!("{}: {e:#}", entry.location));
+                    continue;
+                }
+            };
+
+            if entry.id.is_some_and(|id| self.logins.contains_key(&id))
+                || !seen.insert((
+                    login.name.clone(),
+                    login.username.clone(),
+                    login.password.expose_secret().to_owned(),
+                ))
+            {
+                duplicates.push(format!("{}: `{}`", entry.location, login.name));
+                continue;
+            }
+            logins.push((entry.id, login));
+        }
+
+        println!(
+            "{} {} logins",
+            if args.dry_run {
+                "Would import"
+            } else {
+                "Imported"
+            },
+            logins.len()
+        );
+        for (heading, lines) in [
+            ("Skipped these rows", &skipped),
+            ("Skipped these duplicates", &duplicates),
+            ("Warnings", &warnings),
+        ] {
+            if !lines.is_empty() {
+                println!("{heading} ({}):", lines.len());
+                for line in lines {
+                    println!("  {line}");
+                }
+            }
+        }
+
+        if !args.dry_run {
+            let (with_ids, without_ids): (Vec<_>, Vec<_>) =
+                logins.into_iter().partition(|(id, _)| id.is_some());
+            for (id, login) in with_ids {
+                self.logins
+                    .insert(id.expect("Partitioned by whether there's an ID"), login);
+            }
+            self.append_logins(without_ids.into_iter().map(|(_, login)| login).collect());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn audit_with_args(&self, args: &AuditArgs) -> Result<()> {
+        let findings = audit::audit(&self.logins);
+        match args.format {
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(std::io::stdout().lock(), &findings)
+                    .wrap_err("Failed to write the report as JSON")?;
+                println!();
+            }
+            ReportFormat::Table if findings.is_empty() => {
+                println!("No problems were found");
+            }
+            ReportFormat::Table => {
+                println!("{}", Table::new(&findings).with(Style::rounded()));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn breach_check_with_args(&self, args: &BreachCheckArgs) -> Result<()> {
+        let breaches = breach::check(&self.logins, &args.hibp_file)?;
+        match args.format {
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(std::io::stdout().lock(), &breaches)
+                    .wrap_err("Failed to write the report as JSON")?;
+                println!();
+            }
+            ReportFormat::Table if breaches.is_empty() => {
+                println!("None of the passwords have been seen in a breach");
+            }
+            ReportFormat::Table => {
+                println!("{}", Table::new(&breaches).with(Style::rounded()));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn export_with_args(&self, args: &ExportArgs) -> Result<()> {
+        let mut logins = self.query(None);
+        logins.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+
+        let encrypted = if let ExportFormat::Locket = args.format {
+            let password = if args.password_stdin {
+                read_stdin_secret().wrap_err("Failed to read the password from stdin")?
+            } else {
+                ensure_terminal()?;
+                SecretString::new(
+                    Password::with_theme(&ColorfulTheme::default())
+                        .with_prompt("Enter a password for the export")
+                        .with_confirmation("Confirm the password", "The passwords don't match")
+                        .interact()
+                        .wrap_err("Failed to read the password from console")?,
+                )
+            };
+            let cipher = Cipher::new(password.expose_secret())
+                .wrap_err("Failed to derive the export key")?;
+            Some(self.encode(&cipher)?)
+        } else {
+            eprintln!("WARNING: This export is NOT encrypted. Anybody who can read it can read every password in it,");
+            eprintln!(
+                "WARNING: so keep it somewhere safe, and delete it as soon as you're done with it."
+            );
+            None
+        };
+
+        let mut writer: Box<dyn Write> = match &args.output {
+            Some(path) => Box::new(BufWriter::new(
+                create_private_file(path)
+                    .wrap_err_with(|| format!("Failed to create {}", path.display()))?,
+            )),
+            None => Box::new(std::io::stdout().lock()),
+        };
+
+        match args.format {
+            ExportFormat::Locket => writer
+                .write_all(&encrypted.expect("Encrypted above"))
+                .wrap_err("Failed to write the export")?,
+            ExportFormat::Json => output::write_logins(&mut writer, &logins, Format::Json)?,
+            ExportFormat::Csv => output::write_logins(&mut writer, &logins, Format::Csv)?,
+            ExportFormat::BitwardenJson => {
+                export::write_bitwarden(&mut writer, &logins)?;
+                writeln!(writer).wrap_err("Failed to write the export")?;
+            }
+        }
+        writer.flush().wrap_err("Failed to write the export")?;
+
+        if let Some(path) = &args.output {
+            eprintln!("Exported {} logins to {}", logins.len(), path.display());
+        }
+        Ok(())
+    }
+
     pub fn query(&self, name: Option<&str>) -> Vec<(&Uuid, &Login)> {
         use nucleo_matcher::{
             pattern::{CaseMatching, Pattern},
@@ -251,47 +870,165 @@ impl Database {
             .collect()
     }
 
-    pub(crate) fn query_interactive(&mut self, name: Option<&str>) {
-        if self.logins.is_empty() {
+    // Finds every login with a website on the same site as `url`, see `domain::same_site()`.
+    pub fn match_url(&self, url: &str) -> Result<Vec<(&Uuid, &Login)>> {
+        let url = domain::parse_url(url)?;
+
+        Ok(self
+            .logins
+            .iter()
+            .filter(|(_, login)| {
+                login
+                    .urls
+                    .iter()
+                    .filter_map(|saved| domain::parse_url(saved).ok())
+                    .any(|saved| domain::same_site(&saved, &url))
+            })
+            .collect())
+    }
+
+    pub(crate) fn match_interactive(&self, url: &str) -> Result<()> {
+        let matches: Vec<&Login> = self
+            .match_url(url)
+            .wrap_err("Failed to match logins against the URL")?
+            .into_iter()
+            .map(|(_, login)| login)
+            .collect();
+
+        if matches.is_empty() {
             let data = TableValue::Cell(String::from("No records"));
 
             println!(
                 "{table}",
                 table = PoolTable::from(data).with(Style::rounded())
             );
-            return;
+            return Ok(());
         }
+        println!("{}", Table::new(matches).with(Style::rounded()));
 
-        if let Some(name) = name {
-            // Fix?
-            let matches: Vec<&Login> = self
-                .query(Some(name))
-                .iter()
-                .map(|(_, login)| *login)
-                .collect();
-            if matches.is_empty() {
-                let data = TableValue::Cell(String::from("No records"));
-
-                println!(
-                    "{table}",
-                    table = PoolTable::from(data).with(Style::rounded())
-                );
-                return;
+        Ok(())
+    }
+
+    pub(crate) fn query_with_args(&self, args: &QueryArgs) -> Result<()> {
+        let matches = self.query(args.name.as_deref());
+
+        if let Some(field) = &args.field {
+            let options: Vec<(Uuid, &Login)> =
+                matches.iter().map(|(id, login)| (**id, *login)).collect();
+            if options.is_empty() {
+                bail!("No logins matched");
             }
-            println!("{}", Table::new(matches).with(Style::rounded()));
-        } else {
+            let Some(id) = select_interactive(&options)? else {
+                return Ok(());
+            };
+            let Some(value) = output::field(&id, &self.logins[&id], field) else {
+                bail!("The login has no field called `{field}`");
+            };
+
+            // A trailing newline would end up in whatever the value is piped into.
+            if std::io::stdout().is_terminal() {
+                println!("{value}");
+            } else {
+                print!("{value}");
+            }
+            return Ok(());
+        }
+
+        if let Some(template) = &args.template {
+            return output::print_template(&matches, template);
+        }
+        if !matches!(args.format, Format::Table) {
+            return output::print_logins(&matches, args.format);
+        }
+
+        if matches.is_empty() {
+            let data = TableValue::Cell(String::from("No records"));
+
             println!(
-                "{}",
-                Table::new(self.logins.values()).with(Style::rounded())
+                "{table}",
+                table = PoolTable::from(data).with(Style::rounded())
             );
+            return Ok(());
+        }
+        println!(
+            "{}",
+            Table::new(matches.into_iter().map(|(_, login)| login)).with(Style::rounded())
+        );
+
+        Ok(())
+    }
+
+    // Copies a field of the login matching `args.name` to the clipboard, asking which one if there's more than one.
+    // Returns whether anything was copied.
+    pub(crate) fn copy_interactive(
+        &self,
+        args: &CopyArgs,
+        backend: &clipboard::Backend,
+    ) -> Result<bool> {
+        let options: Vec<(Uuid, &Login)> = self
+            .query(args.name.as_deref())
+            .into_iter()
+            .map(|(id, login)| (*id, login))
+            .collect();
+
+        if options.is_empty() {
+            println!("No logins matched");
+            return Ok(false);
         }
+        let Some(id) = select_interactive(&options)? else {
+            return Ok(false);
+        };
+
+        let login = &self.logins[&id];
+        let Some(value) = output::field(&id, login, &args.field).map(Zeroizing::new) else {
+            bail!("`{}` has no field called `{}`", login.name, args.field);
+        };
+        backend
+            .copy(&value)
+            .wrap_err("Failed to copy to the clipboard")?;
+        eprintln!("Copied the {} of `{}`", args.field, login.name);
+
+        Ok(true)
     }
 
     pub fn remove(&mut self, id: Uuid) -> Option<Login> {
         self.logins.remove(&id)
     }
 
-    pub(crate) fn remove_interactive(&mut self) -> Result<Option<Login>> {
+    // Removes the login given with `--id`, or asks which one to remove, then asks for confirmation unless `--yes` was
+    // given.
+    pub(crate) fn remove_with_args(&mut self, args: &RemoveArgs) -> Result<Option<Login>> {
+        let id = match args.id {
+            Some(id) => {
+                if !self.logins.contains_key(&id) {
+                    bail!("There is no login with the ID {id}");
+                }
+                id
+            }
+            None => match self.remove_interactive()? {
+                Some(id) => id,
+                None => return Ok(None),
+            },
+        };
+
+        if !args.yes {
+            ensure_terminal()?;
+            let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!("Remove `{}`?", self.logins[&id].name))
+                .default(false)
+                .interact()
+                .wrap_err("Failed to read confirmation from console")?;
+            if !confirmed {
+                return Ok(None);
+            }
+        }
+
+        Ok(self.logins.remove(&id))
+    }
+
+    // Asks which login should be removed, returning its ID.
+    fn remove_interactive(&self) -> Result<Option<Uuid>> {
+        ensure_terminal()?;
         let options: Vec<_> = self.logins.iter().collect();
         let choice = FuzzySelect::with_theme(&ColorfulTheme::default())
             .items(
@@ -304,32 +1041,343 @@ impl Database {
             .interact_opt()
             .wrap_err("Failed to read choice of login to be removed from console")?;
 
-        if let Some(index) = choice {
-            let id = *options[index].0;
-            return Ok(self.logins.remove(&id));
+        Ok(choice.map(|index| *options[index].0))
+    }
+
+    // Applies the fields which are present in `patch` to a login. If any of them are invalid, nothing is changed.
+    pub fn update(&mut self, id: Uuid, mut patch: LoginPatch) -> Result<()> {
+        let Some(login) = self.logins.get_mut(&id) else {
+            bail!("There is no login with the ID {id}");
+        };
+
+        if let Some(urls) = &mut patch.urls {
+            *urls = normalise_urls(urls)?;
+        }
+        if let Some(fields) = &mut patch.fields {
+            normalise_field_urls(fields)?;
+        }
+
+        if let Some(name) = patch.name {
+            login.name = name;
+        }
+        if let Some(username) = patch.username {
+            login.username = username;
+        }
+        if let Some(password) = patch.password {
+            login.set_password(password, self.history_limit);
+        }
+        if let Some(urls) = patch.urls {
+            login.urls = urls;
+        }
+        if let Some(notes) = patch.notes {
+            login.notes = notes;
+        }
+        if let Some(tags) = patch.tags {
+            login.tags = tags;
+        }
+        if let Some(fields) = patch.fields {
+            login.fields = fields;
         }
 
-        Ok(None)
+        Ok(())
     }
 
-    pub fn sync(&self) -> Result<()> {
-        let f = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .read(false)
-            .open(&self.path)
-            .wrap_err("Failed to open the database file for sync")?;
-        let mut writer = BufWriter::new(f);
+    pub(crate) fn edit_interactive(&mut self) -> Result<()> {
+        ensure_terminal()?;
+        let options: Vec<_> = self.logins.iter().collect();
+        let choice = FuzzySelect::with_theme(&ColorfulTheme::default())
+            .items(
+                options
+                    .iter()
+                    .map(|(_, login)| login)
+                    .collect::<Vec<&&Login>>()
+                    .as_slice(),
+            )
+            .interact_opt()
+            .wrap_err("Failed to read choice of login to be edited from console")?;
+
+        let Some(index) = choice else {
+            return Ok(());
+        };
+        let id = *options[index].0;
+        let login = &self.logins[&id];
+        let theme = ColorfulTheme::default();
+
+        let name = Input::<String>::with_theme(&theme)
+            .with_prompt("Enter the name for the login")
+            .with_initial_text(&login.name)
+            .allow_empty(true)
+            .interact_text()
+            .wrap_err("Failed to read name from console")?;
+
+        let username = Input::<String>::with_theme(&theme)
+            .with_prompt("Enter the username for this login")
+            .with_initial_text(&login.username)
+            .allow_empty(true)
+            .interact_text()
+            .wrap_err("Failed to read username from console")?;
+
+        let password = if Confirm::with_theme(&theme)
+            .with_prompt("Change the password?")
+            .default(false)
+            .interact()
+            .wrap_err("Failed to read confirmation from console")?
+        {
+            Some(read_password_interactive(&theme)?)
+        } else {
+            None
+        };
+
+        let urls = read_urls_interactive(&theme, &login.urls.join(" "))?;
+
+        self.update(
+            id,
+            LoginPatch {
+                name: Some(name),
+                username: Some(username),
+                password,
+                urls: Some(urls),
+                ..Default::default()
+            },
+        )
+        .wrap_err("Failed to update the login")?;
+
+        let login = self
+            .logins
+            .get_mut(&id)
+            .expect("The login was just updated");
+        login
+            .read_details_interactive(&theme)
+            .wrap_err("Failed to read the details of the login from console")?;
+        login
+            .normalise_urls()
+            .wrap_err("Failed to parse websites")?;
+
+        Ok(())
+    }
+
+    // Generates a one-time password for the login with the given ID, returning `None` if there is no such login, or
+    // it doesn't have one-time passwords set up.
+    pub fn otp(&mut self, id: Uuid) -> Result<Option<OtpCode>> {
+        self.logins
+            .get_mut(&id)
+            .and_then(|login| login.otp.as_mut())
+            .map(Otp::generate)
+            .transpose()
+    }
+
+    pub(crate) fn otp_interactive(&mut self, name: Option<&str>) -> Result<()> {
+        let options: Vec<(Uuid, &Login)> = self
+            .query(name)
+            .into_iter()
+            .filter(|(_, login)| login.otp.is_some())
+            .map(|(id, login)| (*id, login))
+            .collect();
+
+        if options.is_empty() {
+            println!("No logins with one-time passwords matched");
+            return Ok(());
+        }
+        let Some(id) = select_interactive(&options)? else {
+            return Ok(());
+        };
+
+        let code = self
+            .otp(id)
+            .wrap_err("Failed to generate a one-time password")?
+            .expect("Only logins with one-time passwords were offered");
+        match (code.remaining, code.counter) {
+            (Some(remaining), _) => println!("{} (valid for {remaining}s)", code.code),
+            (_, Some(counter)) => println!("{} (counter {counter})", code.code),
+            _ => println!("{}", code.code),
+        }
+
+        Ok(())
+    }
+
+    // Puts back the password at `index` in the history of a login, and moves the current one into the history.
+    pub fn restore_password(&mut self, id: Uuid, index: usize) -> Result<()> {
+        let Some(login) = self.logins.get_mut(&id) else {
+            bail!("There is no login with the ID {id}");
+        };
+        if index >= login.history.len() {
+            bail!(
+                "The login only has {} previous passwords",
+                login.history.len()
+            );
+        }
+
+        let previous = login.history.remove(index);
+        login.set_password(previous.password, self.history_limit);
+        Ok(())
+    }
+
+    // Lists the previous passwords of a login, or restores one of them if `restore` is given. `restore` counts from 1,
+    // as that's what's shown in the list.
+    pub(crate) fn history_interactive(
+        &mut self,
+        name: Option<&str>,
+        restore: Option<usize>,
+        reveal: bool,
+    ) -> Result<()> {
+        let options: Vec<(Uuid, &Login)> = self
+            .query(name)
+            .into_iter()
+            .map(|(id, login)| (*id, login))
+            .collect();
+
+        if options.is_empty() {
+            println!("No logins matched");
+            return Ok(());
+        }
+        let Some(id) = select_interactive(&options)? else {
+            return Ok(());
+        };
+
+        if let Some(number) = restore {
+            let Some(index) = number.checked_sub(1) else {
+                bail!("Previous passwords are numbered from 1");
+            };
+            self.restore_password(id, index)
+                .wrap_err("Failed to restore the password")?;
+            println!("Restored password {number}, the current password is now number 1");
+            return Ok(());
+        }
+
+        let login = &self.logins[&id];
+        if login.history.is_empty() {
+            println!("{} has no previous passwords", login.name);
+            return Ok(());
+        }
+
+        let rows = login
+            .history
+            .iter()
+            .enumerate()
+            .map(|(index, previous)| HistoryRow {
+                number: index + 1,
+                replaced: previous
+                    .replaced
+                    .format("%Y-%m-%d %H:%M:%S UTC")
+                    .to_string(),
+                password: if reveal {
+                    previous.password.expose_secret().to_owned()
+                } else {
+                    previous.password.to_string()
+                },
+            });
+        println!("{}", Table::new(rows).with(Style::rounded()));
+
+        Ok(())
+    }
+
+    // Serialises and encrypts the database into what's written to disk.
+    fn encode(&self, cipher: &Cipher) -> Result<Vec<u8>> {
+        let plaintext = Zeroizing::new(
+            rmp_serde::encode::to_vec_named(&self).wrap_err("Failed to serialise the database")?,
+        );
+        let mut doc = migrations::header();
+        let envelope = cipher
+            .seal(&plaintext, &doc)
+            .wrap_err("Failed to encrypt the database")?;
+        rmp_serde::encode::write_named(&mut doc, &envelope)
+            .wrap_err("Failed to serialise the encrypted database")?;
+        Ok(doc)
+    }
+
+    #[cfg(feature = "web")]
+    pub fn verify_password(&self, password: &str) -> Result<bool> {
+        let Some(cipher) = &self.cipher else {
+            bail!("Tried to check the master password of a database without one");
+        };
+        cipher.verify_password(password)
+    }
+
+    pub fn sync(&mut self) -> Result<()> {
+        let Some(cipher) = &self.cipher else {
+            bail!("Tried to sync a database without a master password");
+        };
+        let doc = self.encode(cipher)?;
 
-        let doc = rmp_serde::encode::to_vec(&self).wrap_err("Failed to serialise the database")?;
+        // Write everything to a temporary file first, and only move it over the real database once it's safely on
+        // disk, so that a crash part way through can never leave us with a half-written database. Whatever's left over
+        // from a sync that didn't finish is thrown away first, so that the new file only ever has our permissions.
+        let tmp_path = sibling_path(&self.path, TEMP_SUFFIX);
+        if let Err(err) = fs::remove_file(&tmp_path) {
+            if err.kind() != ErrorKind::NotFound {
+                return Err(err).wrap_err("Failed to remove an old temporary file");
+            }
+        }
+        let f =
+            create_private_file(&tmp_path).wrap_err("Failed to open a temporary file for sync")?;
+        let mut writer = BufWriter::new(f);
         writer
             .write_all(&doc)
             .wrap_err("Failed to write the database to disk")?;
+        writer
+            .into_inner()
+            .map_err(std::io::IntoInnerError::into_error)
+            .and_then(|f| f.sync_all())
+            .wrap_err("Failed to flush the database to disk")?;
+
+        let has_previous = fs::metadata(&self.path).is_ok_and(|meta| meta.len() > 0);
+        if has_previous && !self.restored_from_backup {
+            let bak_path = sibling_path(&self.path, BACKUP_SUFFIX);
+            if let Err(err) = fs::remove_file(&bak_path) {
+                if err.kind() != ErrorKind::NotFound {
+                    return Err(err).wrap_err("Failed to remove the old database backup");
+                }
+            }
+            // A hard link keeps the previous version around without copying it, but not every filesystem supports
+            // them.
+            if fs::hard_link(&self.path, &bak_path).is_err() {
+                fs::copy(&self.path, &bak_path).wrap_err("Failed to back up the database")?;
+            }
+        }
+
+        fs::rename(&tmp_path, &self.path).wrap_err("Failed to move the new database into place")?;
+        sync_parent_dir(&self.path).wrap_err("Failed to flush the data directory to disk")?;
+        self.restored_from_backup = false;
 
         Ok(())
     }
 }
 
+// An empty backup is no better than no backup at all.
+fn has_backup(path: &Path) -> bool {
+    fs::metadata(sibling_path(path, BACKUP_SUFFIX)).is_ok_and(|meta| meta.len() > 0)
+}
+
+// Creates a file which only we can read, refusing to overwrite anything that's already there.
+pub(crate) fn create_private_file(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+// Appends `suffix` to the file name of `path`, e.g. `locket.db` -> `locket.db.bak`.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+// A rename isn't durable until the directory containing it has been flushed too.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
 impl Display for Login {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Login")
@@ -346,15 +1394,323 @@ impl AsRef<str> for Login {
 }
 
 impl Login {
-    pub fn new(name: String, username: String, password: String) -> Self {
+    #[must_use]
+    pub fn new(name: String, username: String, password: SecretString) -> Self {
         Self {
             name,
             username,
             password,
+            urls: Vec::new(),
+            notes: String::new(),
+            tags: BTreeSet::new(),
+            fields: Vec::new(),
+            otp: None,
+            history: Vec::new(),
+        }
+    }
+
+    // Replaces the password, keeping the old one in the history unless it's the same. Only the newest `limit`
+    // previous passwords are kept.
+    pub fn set_password(&mut self, password: SecretString, limit: usize) {
+        if password.expose_secret() == self.password.expose_secret() {
+            return;
+        }
+
+        let previous = std::mem::replace(&mut self.password, password);
+        self.history.insert(
+            0,
+            PreviousPassword {
+                password: previous,
+                replaced: Utc::now(),
+            },
+        );
+        self.history.truncate(limit);
+    }
+
+    // Asks for the notes, tags and custom fields of a login.
+    fn read_details_interactive(&mut self, theme: &ColorfulTheme) -> Result<()> {
+        self.notes = Input::<String>::with_theme(theme)
+            .with_prompt("Enter any notes for this login (leave empty for none)")
+            .with_initial_text(&self.notes)
+            .allow_empty(true)
+            .interact_text()
+            .wrap_err("Failed to read notes from console")?;
+
+        let tags = Input::<String>::with_theme(theme)
+            .with_prompt(
+                "Enter the tags for this login, separated by commas (leave empty for none)",
+            )
+            .with_initial_text(self.tags.iter().join(", "))
+            .allow_empty(true)
+            .interact_text()
+            .wrap_err("Failed to read tags from console")?;
+        self.tags = tags
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(String::from)
+            .collect();
+
+        // When editing, the existing fields are kept unless they're removed here.
+        let mut fields = Vec::with_capacity(self.fields.len());
+        for field in self.fields.drain(..) {
+            if Confirm::with_theme(theme)
+                .with_prompt(format!("Keep the custom field `{}`?", field.name))
+                .default(true)
+                .interact()
+                .wrap_err("Failed to read confirmation from console")?
+            {
+                fields.push(field);
+            }
+        }
+        self.fields = fields;
+
+        while Confirm::with_theme(theme)
+            .with_prompt("Add a custom field?")
+            .default(false)
+            .interact()
+            .wrap_err("Failed to read confirmation from console")?
+        {
+            self.fields.push(CustomField::read_interactive(theme)?);
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites every URL into its canonical form, e.g. `example.com` -> `https://example.com/`, so that they can be
+    /// used as links.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the URLs isn't valid.
+    pub fn normalise_urls(&mut self) -> Result<()> {
+        self.urls = normalise_urls(&self.urls)?;
+        normalise_field_urls(&mut self.fields)
+    }
+}
+
+impl CustomField {
+    fn read_interactive(theme: &ColorfulTheme) -> Result<Self> {
+        let name = Input::<String>::with_theme(theme)
+            .with_prompt("Enter the name of the field")
+            .interact_text()
+            .wrap_err("Failed to read the name of the field from console")?;
+
+        let kind = Select::with_theme(theme)
+            .with_prompt("What type of field is it?")
+            .items(&["Text", "Hidden", "URL", "Date"])
+            .default(0)
+            .interact()
+            .wrap_err("Failed to read the type of the field from console")?;
+
+        let value = match kind {
+            0 => FieldValue::Text(
+                Input::with_theme(theme)
+                    .with_prompt("Enter the value of the field")
+                    .allow_empty(true)
+                    .interact_text()
+                    .wrap_err("Failed to read the value of the field from console")?,
+            ),
+            1 => FieldValue::Hidden(SecretString::new(
+                Password::with_theme(theme)
+                    .with_prompt("Enter the value of the field")
+                    .allow_empty_password(true)
+                    .interact()
+                    .wrap_err("Failed to read the value of the field from console")?,
+            )),
+            2 => FieldValue::Url(
+                Input::<String>::with_theme(theme)
+                    .with_prompt("Enter the URL")
+                    .validate_with(|url: &String| {
+                        domain::parse_url(url)
+                            .map(|_| ())
+                            .map_err(|e| e.to_string())
+                    })
+                    .interact_text()
+                    .wrap_err("Failed to read the value of the field from console")?,
+            ),
+            _ => FieldValue::Date(
+                Input::<String>::with_theme(theme)
+                    .with_prompt("Enter the date (YYYY-MM-DD)")
+                    .validate_with(|date: &String| {
+                        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                            .map(|_| ())
+                            .map_err(|e| e.to_string())
+                    })
+                    .interact_text()
+                    .wrap_err("Failed to read the value of the field from console")?
+                    .parse()
+                    .wrap_err("Failed to parse the date")?,
+            ),
+        };
+
+        Ok(Self { name, value })
+    }
+}
+
+impl Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Text(text) | Self::Url(text) => write!(f, "{text}"),
+            Self::Hidden(secret) => write!(f, "{secret}"),
+            Self::Date(date) => write!(f, "{date}"),
         }
     }
 }
 
+fn display_urls(urls: &[String]) -> String {
+    urls.join("\n")
+}
+
+fn display_tags(tags: &BTreeSet<String>) -> String {
+    tags.iter().join(", ")
+}
+
+fn display_fields(fields: &[CustomField]) -> String {
+    fields
+        .iter()
+        .map(|field| format!("{}: {}", field.name, field.value))
+        .join("\n")
+}
+
+// Asks for the master password, for when it wasn't given in any other way.
+pub(crate) fn prompt_master_password() -> Result<SecretString> {
+    ensure_terminal().wrap_err(MASTER_PASSWORD_HINT)?;
+    Ok(SecretString::new(
+        Password::with_theme(&ColorfulTheme::default())
+            .with_prompt("Enter the master password")
+            .interact()
+            .wrap_err("Failed to read the master password from console")?,
+    ))
+}
+
+// dialoguer would otherwise wait forever for input which is never going to come, e.g. in a script or CI.
+pub(crate) fn ensure_terminal() -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        bail!(LocketError::NotATerminalError);
+    }
+    Ok(())
+}
+
+// Reads a secret piped into stdin, without the trailing newline which `echo` and friends add.
+fn read_stdin_secret() -> Result<SecretString> {
+    let mut buf = Zeroizing::new(String::new());
+    std::io::stdin()
+        .read_to_string(&mut buf)
+        .wrap_err("Failed to read from stdin")?;
+    Ok(SecretString::new(
+        buf.trim_end_matches(['\r', '\n']).to_owned(),
+    ))
+}
+
+fn read_password_interactive(theme: &ColorfulTheme) -> Result<SecretString> {
+    let generate = Select::with_theme(theme)
+        .with_prompt("How would you like to set the password?")
+        .items(&["Enter a password", "Generate one for me"])
+        .default(0)
+        .interact()
+        .wrap_err("Failed to read choice of password source from console")?
+        == 1;
+
+    if generate {
+        let generated =
+            generator::generate(&Spec::default()).wrap_err("Failed to generate a password")?;
+        println!(
+            "Generated a password with {:.1} bits of entropy",
+            generated.entropy
+        );
+        return Ok(generated.password);
+    }
+
+    Ok(SecretString::new(
+        Password::with_theme(theme)
+            .with_prompt("Enter the password for this login")
+            .allow_empty_password(true)
+            .interact()
+            .wrap_err("Failed to read password from console")?,
+    ))
+}
+
+fn read_urls_interactive(theme: &ColorfulTheme, initial: &str) -> Result<Vec<String>> {
+    let urls = Input::<String>::with_theme(theme)
+        .with_prompt(
+            "Enter the websites for this login, separated by spaces (leave empty for none)",
+        )
+        .with_initial_text(initial)
+        .allow_empty(true)
+        .validate_with(|urls: &String| {
+            urls.split_whitespace()
+                .try_for_each(|url| domain::parse_url(url).map(|_| ()))
+                .map_err(|e| e.to_string())
+        })
+        .interact_text()
+        .wrap_err("Failed to read websites from console")?;
+
+    Ok(urls.split_whitespace().map(String::from).collect())
+}
+
+fn normalise_urls(urls: &[String]) -> Result<Vec<String>> {
+    urls.iter()
+        .map(|url| domain::parse_url(url).map(String::from))
+        .collect()
+}
+
+fn normalise_field_urls(fields: &mut [CustomField]) -> Result<()> {
+    for field in fields {
+        if let FieldValue::Url(url) = &mut field.value {
+            *url = domain::parse_url(url)?.into();
+        }
+    }
+    Ok(())
+}
+
+// Picks one of `options`, only asking if there's more than one. `None` means that the user backed out.
+fn select_interactive(options: &[(Uuid, &Login)]) -> Result<Option<Uuid>> {
+    if let [(id, _)] = options {
+        return Ok(Some(*id));
+    }
+    ensure_terminal()?;
+
+    let choice = FuzzySelect::with_theme(&ColorfulTheme::default())
+        .items(
+            options
+                .iter()
+                .map(|(_, login)| login)
+                .collect::<Vec<&&Login>>()
+                .as_slice(),
+        )
+        .interact_opt()
+        .wrap_err("Failed to read choice of login from console")?;
+
+    Ok(choice.map(|index| options[index].0))
+}
+
+fn default_history_limit() -> usize {
+    DEFAULT_HISTORY_LIMIT
+}
+
+fn default_clipboard_timeout() -> u64 {
+    DEFAULT_CLIPBOARD_TIMEOUT
+}
+
+#[cfg(feature = "web")]
+fn default_bind() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
+}
+
+#[cfg(feature = "web")]
+fn default_session_timeout() -> u64 {
+    DEFAULT_SESSION_TIMEOUT
+}
+
+#[derive(Tabled)]
+struct HistoryRow {
+    #[tabled(rename = "#")]
+    number: usize,
+    replaced: String,
+    password: String,
+}
+
 // A tuple struct which simply allows us to have custom `Deref` behaviour on a `(&Uuid, &Login)`.
 // We need this because of how nucleo works.
 struct LoginAndId<'a>(&'a Uuid, &'a Login);
@@ -365,7 +1721,7 @@ impl<'a> From<(&'a Uuid, &'a Login)> for LoginAndId<'a> {
     }
 }
 
-impl<'a> AsRef<str> for LoginAndId<'a> {
+impl AsRef<str> for LoginAndId<'_> {
     fn as_ref(&self) -> &str {
         &self.1.name
     }
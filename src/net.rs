@@ -1,112 +1,414 @@
 use std::{
+    collections::BTreeSet,
     fs,
-    hint::unreachable_unchecked,
     io::ErrorKind,
+    mem,
+    net::SocketAddr,
+    num::NonZeroUsize,
     path::Path,
     str::FromStr,
     sync::atomic::{AtomicBool, Ordering},
-    sync::Arc,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    thread,
+    time::Duration,
 };
 
-use color_eyre::eyre::{bail, Result, WrapErr};
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use itertools::Itertools;
 use log::{debug, error, info, warn};
-use signal_hook::consts::SIGINT;
+use serde_derive::Serialize;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
 use tiny_http::{Header, Request, Response, StatusCode};
-use url::Url;
+use url::{form_urlencoded, Url};
 use uuid::Uuid;
+use zeroize::Zeroizing;
 
-use crate::models::{Database, Login};
+use crate::audit;
+use crate::crypto;
+use crate::generator::{self, Spec};
+use crate::models::{Config, CustomField, Database, FieldValue, Login, LoginPatch};
+use crate::secret::SecretString;
+use crate::session::{self, Sessions};
+use crate::threadpool::Threadpool;
+use crate::tls;
+use crate::vault::Session;
 
-pub fn serve(db: &mut Database, port: u16, lck_path: &Path) -> Result<()> {
+// How many requests can be waiting for a worker before any more are turned away.
+const QUEUE_CAPACITY: usize = 64;
+// How long to wait for a request before checking whether a signal has come in.
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+// Everything that the workers share between them. Most requests only read the database, so they can be handled
+// side by side, and only the ones that change something have to wait for everybody else.
+struct State {
+    db: RwLock<Database>,
+    sessions: Mutex<Sessions>,
+    settings: RwLock<Settings>,
+    base_url: Url,
+}
+
+// What a login looks like to `/api/v1/query` and `/api/v1/match`. The one-time password secret and the password
+// history each have their own endpoint, so neither is sent along with every login.
+#[derive(Serialize)]
+struct ListedLogin<'a> {
+    name: &'a str,
+    username: &'a str,
+    password: &'a SecretString,
+    urls: &'a [String],
+    notes: &'a str,
+    tags: &'a BTreeSet<String>,
+    fields: &'a [CustomField],
+    has_otp: bool,
+}
+
+// The parts of the config which take effect straight away when it's reloaded.
+struct Settings {
+    web_passphrase: Option<String>,
+    // The names that the server can be reached by, which the `Host` and `Origin` headers have to match.
+    hosts: Vec<String>,
+}
+
+// A request that panics while holding one of the locks poisons it, but nothing is written to disk until the database
+// is synced, so that's no reason to take down every request after it as well.
+impl State {
+    fn db(&self) -> RwLockReadGuard<'_, Database> {
+        self.db.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn db_mut(&self) -> RwLockWriteGuard<'_, Database> {
+        self.db.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, Sessions> {
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn settings(&self) -> RwLockReadGuard<'_, Settings> {
+        self.settings.read().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Serves the web interface until a SIGINT or SIGTERM comes in, at which point every request that has already been
+// received is finished off before returning. A SIGHUP calls `reload` to read the config again.
+pub fn serve(
+    session: &mut Session,
+    data_dir: &Path,
+    reload: impl Fn() -> Result<Config>,
+) -> Result<()> {
+    let config = &session.config;
     let should_shutdown = Arc::new(AtomicBool::new(false));
-    signal_hook::flag::register(SIGINT, Arc::clone(&should_shutdown))
-        .wrap_err("Failed to register the shutdown bool")?;
-    let ip = format!("127.0.0.1:{port}");
-    let server = tiny_http::Server::http(&ip)
-        .map_err(|e| color_eyre::eyre::eyre!(e))
-        .wrap_err_with(|| format!("Failed to start server at {ip}"))?;
-
-    info!("Serving webpage at {ip}");
-    for request in server.incoming_requests() {
-        use tiny_http::Method as M;
-        let url = match Url::from_str(&format!("https://{ip}"))
-            .expect("Please don't put any rubbish in this url")
-            .join(request.url())
-        {
-            Ok(url) => url,
-            Err(e) => {
-                error!(
-                    "Failed to parse a url: `{}`, with err: {}",
-                    request.url(),
-                    e
-                );
-                std::process::exit(1)
+    for signal in [SIGINT, SIGTERM] {
+        // If shutting down is taking too long, a second signal kills us straight away.
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&should_shutdown))
+            .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&should_shutdown)))
+            .wrap_err("Failed to register the shutdown bool")?;
+    }
+    let should_reload = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGHUP, Arc::clone(&should_reload))
+        .wrap_err("Failed to register the reload bool")?;
+
+    // The names that the server can always be reached by, on top of the ones in the config.
+    let mut own_hosts = Vec::new();
+    let (server, base_url) = if let Some(path) = &config.socket_path {
+        // Only we can connect to the socket, so there's nothing for TLS to add. Anything in front of it, like a reverse
+        // proxy, can do TLS itself.
+        let server = tiny_http::Server::from_listener(bind_unix(path)?, None)
+            .map_err(|e| eyre!(e))
+            .wrap_err_with(|| format!("Failed to start server at {}", path.display()))?;
+        println!("Serving webpage at {}", path.display());
+        own_hosts.push(String::from("localhost"));
+        (server, String::from("http://localhost"))
+    } else {
+        let addr = SocketAddr::new(config.bind, config.port);
+        let (ssl, fingerprint) =
+            tls::load(config, data_dir).wrap_err("Failed to load the TLS certificate")?;
+        let server = tiny_http::Server::https(addr, ssl)
+            .map_err(|e| eyre!(e))
+            .wrap_err_with(|| format!("Failed to start server at {addr}"))?;
+        // This is printed rather than logged, as it's needed to check the certificate in the browser.
+        println!("Serving webpage at https://{addr}");
+        println!("The certificate's SHA-256 fingerprint is {fingerprint}");
+        own_hosts.push(addr.to_string());
+        own_hosts.push(format!("localhost:{}", config.port));
+        if config.bind.is_unspecified() && config.allowed_hosts.is_empty() {
+            eprintln!(
+                "Only localhost:{} will be let in, add the names that the server is reached by to `allowed_hosts`",
+                config.port
+            );
+        }
+        (server, format!("https://{addr}"))
+    };
+
+    // The database is lent to the workers for as long as the server is running, and handed back at the end.
+    let state = Arc::new(State {
+        db: RwLock::new(mem::take(&mut session.db)),
+        sessions: Mutex::new(Sessions::new(Duration::from_secs(config.session_timeout))),
+        settings: RwLock::new(Settings::new(config, &own_hosts)),
+        base_url: Url::from_str(&base_url).expect("Please don't put any rubbish in this url"),
+    });
+
+    let workers = thread::available_parallelism().map_or(4, NonZeroUsize::get);
+    let pool = Threadpool::new(workers, QUEUE_CAPACITY);
+    let result = loop {
+        if should_shutdown.load(Ordering::Relaxed) {
+            println!("Shutting down");
+            break Ok(());
+        }
+        if should_reload.swap(false, Ordering::Relaxed) {
+            match reload() {
+                Ok(new_config) => apply_reload(&state, config, &new_config, &own_hosts),
+                Err(err) => {
+                    error!("Failed to reload the config, the old one is still in use: {err:#}");
+                }
             }
+        }
+
+        let request = match server.recv_timeout(SIGNAL_CHECK_INTERVAL) {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            // A signal interrupts the wait, which isn't an error at all.
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => break Err(err).wrap_err("Failed to receive a request"),
         };
-        // TODO: Go through all of these functions, and check that they follow the proper behaviour, returning correct status codes, etc.
-        match (request.method(), url.path()) {
-            (
-                M::Get,
-                "/" | "/new" | "/index.css" | "/query.js" | "/query.js.map" | "/form.js"
-                | "/form.js.map",
-            ) => serve_static(request),
-            (M::Get, "/query") => serve_query_page(
-                request,
-                url.query_pairs()
-                    .find(|query| &query.0 == "query")
-                    .map(|query| query.1)
-                    .as_deref(),
-                db,
-            ),
-            (M::Get, "/api/v1/query") => serve_query(
-                request,
-                url.query_pairs()
-                    .find(|query| &query.0 == "query")
-                    .map(|query| query.1)
-                    .as_deref(),
-                db,
-            ),
-            (M::Get, "/api/v1/sync") => {
-                db.sync()
-                    .wrap_err("Failed to sync database after it was requested via API")?;
-                let Err(err) = request.respond(
-                    Response::from_string(StatusCode(204).default_reason_phrase())
-                        .with_status_code(204),
-                ) else {
-                    continue;
-                };
 
+        // Turning requests away straight off is better than letting them pile up without end, and then having the
+        // browser time out on them anyway.
+        if pool.is_full() {
+            warn!(
+                "Turned away a request to `{}`, as too many are waiting already",
+                request.url()
+            );
+            serve_status(request, 503);
+        } else {
+            let state = Arc::clone(&state);
+            pool.exec(move || handle(request, &state));
+        }
+    };
+
+    // Nothing new is let in from here on. Dropping the pool then waits for the workers to finish whatever they had
+    // already been given, after which nothing else holds on to the state.
+    drop(server);
+    drop(pool);
+    if let Some(path) = &config.socket_path {
+        if let Err(err) = fs::remove_file(path) {
+            warn!("Failed to remove the socket at {}: {err}", path.display());
+        }
+    }
+
+    let Ok(state) = Arc::try_unwrap(state) else {
+        unreachable!("The workers have all finished, so they can't be holding the state");
+    };
+    session.db = state
+        .db
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner);
+
+    // The error is passed straight back up, so whatever was changed through the web interface would never be synced
+    // by the caller otherwise.
+    if result.is_err() {
+        if let Err(err) = session.sync() {
+            error!("Failed to sync the database after the server stopped: {err:#}");
+        }
+    }
+    result
+}
+
+impl Settings {
+    fn new(config: &Config, own_hosts: &[String]) -> Self {
+        Self {
+            web_passphrase: config.web_passphrase.clone(),
+            hosts: config
+                .allowed_hosts
+                .iter()
+                .chain(own_hosts)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+// Switches over to a config that was read again after a SIGHUP. Where and how the server listens, and which database
+// it uses, can't be changed without starting it again, so those are left alone.
+fn apply_reload(state: &State, old: &Config, new: &Config, own_hosts: &[String]) {
+    *state
+        .settings
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Settings::new(new, own_hosts);
+    state
+        .sessions()
+        .set_timeout(Duration::from_secs(new.session_timeout));
+    state.db_mut().history_limit = new.history_limit;
+
+    if new.path != old.path
+        || new.port != old.port
+        || new.bind != old.bind
+        || new.socket_path != old.socket_path
+        || new.tls_cert != old.tls_cert
+        || new.tls_key != old.tls_key
+    {
+        println!("Reloaded the config, but changes to the database path, address, port, socket and certificate need a restart");
+    } else {
+        println!("Reloaded the config");
+    }
+}
+
+// Handles a single request, on one of the workers.
+#[allow(clippy::too_many_lines)]
+fn handle(request: Request, state: &State) {
+    use tiny_http::Method as M;
+    let url = match state.base_url.join(request.url()) {
+        Ok(url) => url,
+        Err(e) => {
+            debug!(
+                "Failed to parse a url: `{}`, with err: {}",
+                request.url(),
+                e
+            );
+            serve_status(request, 400);
+            return;
+        }
+    };
+
+    if let Err(reason) = check_origin(&request, &state.settings().hosts) {
+        warn!("Rejected a request to `{}`: {reason}", url.path());
+        serve_status(request, 403);
+        return;
+    }
+
+    // Everything but the login page needs a session. The stylesheet is let through as well, since the login page
+    // needs it, and there's nothing secret in it.
+    let is_public = matches!(
+        (request.method(), url.path()),
+        (M::Get | M::Post, "/login") | (M::Get, "/index.css")
+    );
+    if !is_public && !session::token(&request).is_some_and(|token| state.sessions().touch(token)) {
+        debug!("Rejected a request to `{}` without a session", url.path());
+        // Pages send people to the login page, whereas the API just says no.
+        if *request.method() == M::Get && !url.path().starts_with("/api/") {
+            redirect(request, "/login", &[]);
+        } else {
+            serve_status(request, 401);
+        }
+        return;
+    }
+
+    // Logging out is left out, as the worst that another site could do with it is log somebody out, and it can't
+    // even do that past the `Origin` check.
+    let is_mutating = !matches!(request.method(), M::Get | M::Head);
+    if is_mutating && !is_public && url.path() != "/logout" {
+        if let Err(reason) = check_csrf_token(&request, &state.sessions()) {
+            warn!("Rejected a request to `{}`: {reason}", url.path());
+            serve_status(request, 403);
+            return;
+        }
+    }
+
+    // TODO: Go through all of these functions, and check that they follow the proper behaviour, returning correct status codes, etc.
+    match (request.method(), url.path()) {
+        (M::Get, "/login") => serve_login_page(request, "", 200),
+        (M::Post, "/login") => login(request, state),
+        (M::Post, "/logout") => logout(request, &mut state.sessions()),
+        (
+            M::Get,
+            "/" | "/new" | "/index.css" | "/query.js" | "/query.js.map" | "/form.js"
+            | "/form.js.map" | "/csrf.js" | "/csrf.js.map",
+        ) => serve_static(request, url.path()),
+        (M::Get, "/query") => serve_query_page(
+            request,
+            url.query_pairs()
+                .find(|query| &query.0 == "query")
+                .map(|query| query.1)
+                .as_deref(),
+            &state.db(),
+        ),
+        (M::Get, "/api/v1/query") => serve_query(
+            request,
+            url.query_pairs()
+                .find(|query| &query.0 == "query")
+                .map(|query| query.1)
+                .as_deref(),
+            &state.db(),
+        ),
+        // Syncing always writes to the same temporary file, so two syncs at once could leave a half-written vault
+        // behind. The write lock makes sure that only one happens at a time.
+        (M::Post, "/api/v1/sync") => {
+            if let Err(e) = state.db_mut().sync() {
+                error!("Failed to sync database after it was requested via API: {e:?}");
+                serve_status(request, 500);
+                return;
+            }
+            if let Err(err) = request.respond(
+                Response::from_string(StatusCode(204).default_reason_phrase())
+                    .with_status_code(204),
+            ) {
                 warn!("Failed to respond to a request: {err:#?}");
             }
-            (M::Post, "/api/v1/new") => add_new(request, db),
-            (M::Delete, "/api/v1/remove") => remove_login(
+        }
+        (M::Get, "/api/v1/match") => {
+            serve_match(request, query_param(&url, "url").as_deref(), &state.db());
+        }
+        (M::Get, "/api/v1/generate") => serve_generate(request, &url),
+        (M::Get, "/api/v1/audit") => serve_audit(request, &state.db()),
+        // Getting a HOTP code moves its counter on, so it's a `POST`, which means it needs the CSRF token too.
+        (M::Post, "/api/v1/otp") => {
+            serve_otp(
                 request,
-                url.query_pairs()
-                    .find(|query| &query.0 == "id")
-                    .map(|query| query.1)
-                    .as_deref(),
-                db,
-            ),
-            _ => {
-                info!("404 served: {}", url.path());
-                serve_404(request);
-            }
+                query_param(&url, "id").as_deref(),
+                &mut state.db_mut(),
+            );
+        }
+        (M::Get, "/api/v1/history") => {
+            serve_history(request, query_param(&url, "id").as_deref(), &state.db());
+        }
+        (M::Patch, "/api/v1/login") => {
+            update_login(
+                request,
+                query_param(&url, "id").as_deref(),
+                &mut state.db_mut(),
+            );
+        }
+        (M::Post, "/api/v1/new") => add_new(request, &mut state.db_mut()),
+        (M::Delete, "/api/v1/remove") => remove_login(
+            request,
+            url.query_pairs()
+                .find(|query| &query.0 == "id")
+                .map(|query| query.1)
+                .as_deref(),
+            &mut state.db_mut(),
+        ),
+        _ => {
+            info!("404 served: {}", url.path());
+            serve_404(request);
         }
+    }
+}
 
-        if should_shutdown.load(Ordering::Relaxed) {
-            db.sync().wrap_err("Failed to sync database to disk")?;
-            if let Err(err) = fs::remove_file(lck_path) {
-                match err.kind() {
-                    ErrorKind::NotFound => {
-                        eprintln!("Tried to remove the lockfile, but it wasn't present");
-                        std::process::exit(1);
-                    }
-                    _ => bail!("Failed to remove the lockfile: {}", err),
-                }
-            };
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // A socket left behind by a crash stops us from binding to it again, and as we hold the lockfile, nothing else
+    // can be using it.
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            fs::remove_file(path).wrap_err("Failed to remove the old socket")?;
         }
+        Ok(_) => bail!("{} already exists, and isn't a socket", path.display()),
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err).wrap_err("Failed to check whether the socket already exists"),
     }
-    Ok(())
+
+    // Setting the umask, rather than changing the permissions afterwards, means that there's never a moment where
+    // somebody else could connect. Nothing else is running yet, so changing it for the whole process is fine.
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = std::os::unix::net::UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+    listener.wrap_err_with(|| format!("Failed to bind to {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_path: &Path) -> Result<std::net::TcpListener> {
+    bail!("Unix sockets can only be used on Unix")
 }
 
 // In debug mode, we can do a sort of "hot-reloading", by just reopening the same files
@@ -114,8 +416,8 @@ pub fn serve(db: &mut Database, port: u16, lck_path: &Path) -> Result<()> {
 // is editing this project's code, and doesn't have these files in the right places, it's
 // their fault, and it's my project so I can do what I like :^).
 #[cfg(debug_assertions)]
-fn serve_static(request: Request) {
-    match request.url() {
+fn serve_static(request: Request, path: &str) {
+    match path {
         "/" => serve_bytes(
             request,
             &fs::read("src/web/index.html").expect("Failed to open index.html")[..],
@@ -151,15 +453,25 @@ fn serve_static(request: Request) {
             &fs::read("dist/form.js.map").expect("Failed to open form.js.map")[..],
             "application/javascript; charset=utf8",
         ),
-        _ => unsafe { unreachable_unchecked() },
-    };
+        "/csrf.js" => serve_bytes(
+            request,
+            &fs::read("dist/csrf.js").expect("Failed to open csrf.js")[..],
+            "application/javascript; charset=utf8",
+        ),
+        "/csrf.js.map" => serve_bytes(
+            request,
+            &fs::read("dist/csrf.js.map").expect("Failed to open csrf.js.map")[..],
+            "application/javascript; charset=utf8",
+        ),
+        _ => serve_404(request),
+    }
 }
 
 // Release mode version of the previous function. Here, it uses `include_bytes!()` to
 // pack the content of the files into the binary.
 #[cfg(not(debug_assertions))]
-fn serve_static(request: Request) {
-    match request.url() {
+fn serve_static(request: Request, path: &str) {
+    match path {
         "/" => serve_bytes(
             request,
             &include_bytes!("web/index.html")[..],
@@ -195,8 +507,93 @@ fn serve_static(request: Request) {
             &include_bytes!("../dist/form.js.map")[..],
             "application/javascript; charset=utf8",
         ),
-        _ => unsafe { unreachable_unchecked() },
+        "/csrf.js" => serve_bytes(
+            request,
+            &include_bytes!("../dist/csrf.js")[..],
+            "application/javascript; charset=utf8",
+        ),
+        "/csrf.js.map" => serve_bytes(
+            request,
+            &include_bytes!("../dist/csrf.js.map")[..],
+            "application/javascript; charset=utf8",
+        ),
+        _ => serve_404(request),
+    }
+}
+
+// Like the query page, this is formatted rather than hot-reloaded, as it has the error message put into it.
+fn serve_login_page(request: Request, error: &str, status: u16) {
+    let header =
+        Header::from_bytes("Content-Type", "text/html").expect("Don't put rubbish in here please");
+    let response = Response::from_string(format!(include_str!("web/login.html"), error = error))
+        .with_header(header)
+        .with_status_code(status);
+
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to respond to a request: {e:#?}");
+    }
+}
+
+// Checks the password from the login page, and starts a session if it's right. If a passphrase has been set for the
+// web interface, only that is accepted, otherwise it's the master password.
+fn login(mut request: Request, state: &State) {
+    let mut body = Zeroizing::new(String::new());
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        debug!("Failed to read the body of a login request: {e}");
+        serve_status(request, 400);
+        return;
+    }
+    let password = Zeroizing::new(
+        form_urlencoded::parse(body.as_bytes())
+            .find(|(name, _)| name == "password")
+            .map(|(_, password)| password.into_owned())
+            .unwrap_or_default(),
+    );
+
+    // Checking the password takes a while, so the settings aren't kept locked in the meantime.
+    let web_passphrase = state.settings().web_passphrase.clone();
+    let is_correct = match &web_passphrase {
+        Some(hash) => crypto::verify_password_hash(hash, &password),
+        None => state.db().verify_password(&password),
     };
+    match is_correct {
+        Ok(true) => {
+            info!("Somebody logged in to the web interface");
+            let (token, csrf_token) = state.sessions().create();
+            redirect(request, "/", &session::cookies(&token, &csrf_token));
+        }
+        Ok(false) => {
+            warn!("Somebody failed to log in to the web interface with the wrong password");
+            serve_login_page(request, "That password isn't right", 401);
+        }
+        Err(e) => {
+            error!("Failed to check the password for a login: {e}");
+            serve_status(request, 500);
+        }
+    }
+}
+
+fn logout(request: Request, sessions: &mut Sessions) {
+    if let Some(token) = session::token(&request) {
+        sessions.remove(token);
+    }
+    redirect(request, "/login", &session::expired_cookies());
+}
+
+fn redirect(request: Request, location: &str, cookies: &[String]) {
+    let mut response = Response::empty(303).with_header(
+        Header::from_bytes("Location", location).expect("Don't put rubbish in here please"),
+    );
+    for cookie in cookies {
+        response.add_header(
+            Header::from_bytes("Set-Cookie", cookie.as_str())
+                .expect("Don't put rubbish in here please"),
+        );
+    }
+
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to respond to a request: {e:#?}");
+    }
 }
 
 fn serve_bytes(request: Request, content: &[u8], content_type: &str) {
@@ -214,7 +611,7 @@ fn serve_bytes(request: Request, content: &[u8], content_type: &str) {
 // we just ignore all headers, and send back `application/json`.
 // TODO: Maybe look at checking the header to at least see if JSON was requested, and if not return 415 with `Accept-Post` set.
 fn serve_query(request: Request, query: Option<&str>, db: &Database) {
-    let matches = db.query(query);
+    let matches = listed(db.query(query));
     let body = serde_json::ser::to_string(&matches);
 
     if let Err(e) = body {
@@ -238,7 +635,77 @@ fn serve_query(request: Request, query: Option<&str>, db: &Database) {
 
     if let Err(e) = request.respond(response) {
         warn!("Failed to respond to a request: {e:#?}");
+    }
+}
+
+// Like `serve_query()`, but returns the logins for the same site as the `url` parameter.
+fn serve_match(request: Request, url: Option<&str>, db: &Database) {
+    let matches = match url.map(|url| db.match_url(url)) {
+        Some(Ok(matches)) => listed(matches),
+        Some(Err(e)) => {
+            debug!("A request to `/api/v1/match` contained an invalid URL: {e}");
+            let response = Response::from_string(StatusCode(400).default_reason_phrase())
+                .with_status_code(400);
+            if let Err(e) = request.respond(response) {
+                warn!("Failed to respond to a request: {e:#?}");
+            }
+            return;
+        }
+        None => {
+            debug!("A request to `/api/v1/match` contained no URL");
+            let response = Response::from_string(StatusCode(400).default_reason_phrase())
+                .with_status_code(400);
+            if let Err(e) = request.respond(response) {
+                warn!("Failed to respond to a request: {e:#?}");
+            }
+            return;
+        }
     };
+
+    let body = match serde_json::ser::to_string(&matches) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Failed to serialise match results into JSON: {e}");
+            if let Err(e) = request.respond(
+                Response::from_string(StatusCode(500).default_reason_phrase())
+                    .with_status_code(500),
+            ) {
+                warn!("Failed to respond to a request: {e:#?}");
+            }
+            return;
+        }
+    };
+
+    let header = Header::from_bytes("Content-Type", "application/json")
+        .expect("Don't put rubbish in here please");
+    let response = Response::from_string(body)
+        .with_header(header)
+        .with_status_code(200);
+
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to respond to a request: {e:#?}");
+    }
+}
+
+fn listed<'a>(logins: Vec<(&'a Uuid, &'a Login)>) -> Vec<(&'a Uuid, ListedLogin<'a>)> {
+    logins
+        .into_iter()
+        .map(|(id, login)| {
+            (
+                id,
+                ListedLogin {
+                    name: &login.name,
+                    username: &login.username,
+                    password: &login.password,
+                    urls: &login.urls,
+                    notes: &login.notes,
+                    tags: &login.tags,
+                    fields: &login.fields,
+                    has_otp: login.otp.is_some(),
+                },
+            )
+        })
+        .collect()
 }
 
 // This function currently doesn't support the "hot-reloading" that the other static files do. This
@@ -250,9 +717,15 @@ fn serve_query_page(request: Request, query: Option<&str>, db: &Database) {
     for login in logins {
         let card = format!(
             include_str!("web/card.html"),
-            name = login.1.name,
-            username = login.1.username,
-            password = login.1.password,
+            name = escape_html(&login.1.name),
+            username = escape_html(&login.1.username),
+            password = escape_html(login.1.password.expose_secret()),
+            details = card_details(login.1),
+            otp = if login.1.otp.is_some() {
+                format!(include_str!("web/otp.html"), id = login.0.simple())
+            } else {
+                String::new()
+            },
             id = login.0.simple()
         );
         grids.push_str(&card);
@@ -266,55 +739,212 @@ fn serve_query_page(request: Request, query: Option<&str>, db: &Database) {
 
     if let Err(e) = request.respond(response) {
         warn!("Failed to respond to a request: {e:#?}");
+    }
+}
+
+// Accepts the same options as `locket generate`, e.g. `?length=32&symbols=false`, or `?words=6&separator=.` for a
+// passphrase. Anything that isn't given falls back to the defaults.
+fn serve_generate(request: Request, url: &Url) {
+    let spec = match generate_spec(url) {
+        Ok(spec) => spec,
+        Err(e) => {
+            debug!("A request to `/api/v1/generate` had invalid parameters: {e}");
+            let response = Response::from_string(StatusCode(400).default_reason_phrase())
+                .with_status_code(400);
+            if let Err(e) = request.respond(response) {
+                warn!("Failed to respond to a request: {e:#?}");
+            }
+            return;
+        }
     };
+
+    let generated = match generator::generate(&spec) {
+        Ok(generated) => generated,
+        Err(e) => {
+            debug!("Failed to generate a password: {e}");
+            let response = Response::from_string(StatusCode(400).default_reason_phrase())
+                .with_status_code(400);
+            if let Err(e) = request.respond(response) {
+                warn!("Failed to respond to a request: {e:#?}");
+            }
+            return;
+        }
+    };
+
+    let body = serde_json::json!({
+        "password": generated.password.expose_secret(),
+        "entropy": generated.entropy,
+    });
+    let header = Header::from_bytes("Content-Type", "application/json")
+        .expect("Don't put rubbish in here please");
+    let response = Response::from_string(body.to_string())
+        .with_header(header)
+        .with_status_code(200);
+
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to respond to a request: {e:#?}");
+    }
 }
 
-fn add_new(mut request: Request, db: &mut Database) {
-    let body_length = request.body_length().unwrap_or(0);
-    let mut buf: Vec<u8> = Vec::with_capacity(body_length);
-    let Some(content_type_header) = request
-        .headers()
-        .iter()
-        .find(|header| header.field.as_str() == "Content-Type")
-    else {
-        debug!("A request was made to `/api/v1/new` without a `Content-Type` header");
-        let response =
-            Response::from_string(StatusCode(415).default_reason_phrase()).with_status_code(415);
-        if let Err(e) = request.respond(response) {
-            warn!("Failed to respond to a request: {e:#?}");
+fn generate_spec(url: &Url) -> Result<Spec> {
+    let param = |name: &str| query_param(url, name);
+    let flag = |name: &str, default: bool| -> Result<bool> {
+        param(name).map_or(Ok(default), |value| {
+            value
+                .parse()
+                .wrap_err_with(|| format!("`{name}` must be `true` or `false`"))
+        })
+    };
+
+    if let Some(words) = param("words") {
+        let words = if words.is_empty() {
+            generator::DEFAULT_WORDS
+        } else {
+            words.parse().wrap_err("`words` must be a number")?
+        };
+        if words > generator::MAX_WORDS {
+            bail!("`words` can be at most {}", generator::MAX_WORDS);
         }
+        return Ok(Spec::Passphrase {
+            words,
+            separator: param("separator").unwrap_or_else(|| String::from("-")),
+        });
+    }
+
+    let length = param("length").map_or(Ok(generator::DEFAULT_LENGTH), |length| {
+        length.parse().wrap_err("`length` must be a number")
+    })?;
+    if length > generator::MAX_LENGTH {
+        bail!("`length` can be at most {}", generator::MAX_LENGTH);
+    }
+    Ok(Spec::Password {
+        length,
+        lowercase: flag("lowercase", true)?,
+        uppercase: flag("uppercase", true)?,
+        digits: flag("digits", true)?,
+        symbols: flag("symbols", true)?,
+        exclude_ambiguous: flag("exclude_ambiguous", false)?,
+    })
+}
+
+// Generating an HOTP code moves its counter on, which is why this needs the database mutably.
+fn serve_otp(request: Request, id: Option<&str>, db: &mut Database) {
+    let Some(id) = id.and_then(|id| Uuid::parse_str(id).ok()) else {
+        debug!("A request to `/api/v1/otp` contained no ID, or an invalid one");
+        serve_404(request);
         return;
     };
 
-    if content_type_header.value != "application/json" {
-        debug!("A request was made to `/api/v1/new` without a valid `Content-Type` of `application/json`");
-        let response =
-            Response::from_string(StatusCode(415).default_reason_phrase()).with_status_code(415);
-        if let Err(e) = request.respond(response) {
-            warn!("Failed to respond to a request: {e:#?}");
+    let code = match db.otp(id) {
+        Ok(Some(code)) => code,
+        Ok(None) => {
+            debug!("A one-time password was requested for a login without one");
+            serve_404(request);
+            return;
         }
-        return;
+        Err(e) => {
+            warn!("Failed to generate a one-time password: {e}");
+            if let Err(e) = request.respond(
+                Response::from_string(StatusCode(500).default_reason_phrase())
+                    .with_status_code(500),
+            ) {
+                warn!("Failed to respond to a request: {e:#?}");
+            }
+            return;
+        }
+    };
+
+    let body = match serde_json::ser::to_string(&code) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Failed to serialise a one-time password into JSON: {e}");
+            if let Err(e) = request.respond(
+                Response::from_string(StatusCode(500).default_reason_phrase())
+                    .with_status_code(500),
+            ) {
+                warn!("Failed to respond to a request: {e:#?}");
+            }
+            return;
+        }
+    };
+
+    let header = Header::from_bytes("Content-Type", "application/json")
+        .expect("Don't put rubbish in here please");
+    let response = Response::from_string(body)
+        .with_header(header)
+        .with_status_code(200);
+
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to respond to a request: {e:#?}");
     }
+}
 
-    if let Err(e) = request.as_reader().read_to_end(&mut buf) {
-        info!("Could not read the body of the request: {e:#?}");
-        let response =
-            Response::from_string(StatusCode(415).default_reason_phrase()).with_status_code(415);
-        if let Err(e) = request.respond(response) {
-            warn!("Failed to respond to a request: {e:#?}");
+fn serve_audit(request: Request, db: &Database) {
+    let body = match serde_json::ser::to_string(&audit::audit(&db.logins)) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Failed to serialise an audit report into JSON: {e}");
+            serve_status(request, 500);
+            return;
         }
+    };
+
+    let header = Header::from_bytes("Content-Type", "application/json")
+        .expect("Don't put rubbish in here please");
+    let response = Response::from_string(body)
+        .with_header(header)
+        .with_status_code(200);
+
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to respond to a request: {e:#?}");
+    }
+}
+
+fn serve_history(request: Request, id: Option<&str>, db: &Database) {
+    let Some(login) = id
+        .and_then(|id| Uuid::parse_str(id).ok())
+        .and_then(|id| db.logins.get(&id))
+    else {
+        debug!("A request to `/api/v1/history` contained no ID, or one which doesn't exist");
+        serve_404(request);
         return;
+    };
+
+    let body = match serde_json::ser::to_string(&login.history) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Failed to serialise a password history into JSON: {e}");
+            if let Err(e) = request.respond(
+                Response::from_string(StatusCode(500).default_reason_phrase())
+                    .with_status_code(500),
+            ) {
+                warn!("Failed to respond to a request: {e:#?}");
+            }
+            return;
+        }
+    };
+
+    let header = Header::from_bytes("Content-Type", "application/json")
+        .expect("Don't put rubbish in here please");
+    let response = Response::from_string(body)
+        .with_header(header)
+        .with_status_code(200);
+
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to respond to a request: {e:#?}");
     }
+}
 
-    let content = match String::from_utf8(buf) {
+fn add_new(mut request: Request, db: &mut Database) {
+    let content = match read_json_body(&mut request) {
         Ok(content) => content,
-        Err(e) => {
-            debug!("The body of a request could not be interpreted as UTF-8: {e:#?}");
+        Err(status) => {
+            serve_status(request, status);
             return;
         }
     };
 
-    let logins = match serde_json::de::from_str::<Vec<Login>>(&content) {
+    let mut logins = match serde_json::de::from_str::<Vec<Login>>(&content) {
         Ok(logins) => logins,
         Err(e) => {
             info!("Failed to parse login from request: {e}");
@@ -327,12 +957,59 @@ fn add_new(mut request: Request, db: &mut Database) {
         }
     };
 
+    if let Err(e) = logins.iter_mut().try_for_each(Login::normalise_urls) {
+        info!("A login in a request contained an invalid URL: {e}");
+        let response =
+            Response::from_string(StatusCode(400).default_reason_phrase()).with_status_code(400);
+        if let Err(e) = request.respond(response) {
+            warn!("Failed to respond to a request: {e:#?}");
+        }
+        return;
+    }
+
     db.append_logins(logins);
     if let Err(e) = request.respond(
         Response::from_string(StatusCode(201).default_reason_phrase()).with_status_code(201),
     ) {
         warn!("Failed to respond to a request: {e:#?}");
+    }
+}
+
+// Applies a partial update to a login, e.g. `{"username": "alice"}` only changes the username.
+fn update_login(mut request: Request, id: Option<&str>, db: &mut Database) {
+    let Some(id) = id
+        .and_then(|id| Uuid::parse_str(id).ok())
+        .filter(|id| db.logins.contains_key(id))
+    else {
+        debug!("A PATCH request contained no ID, or one which doesn't exist");
+        serve_404(request);
+        return;
+    };
+
+    let content = match read_json_body(&mut request) {
+        Ok(content) => content,
+        Err(status) => {
+            serve_status(request, status);
+            return;
+        }
+    };
+
+    let patch = match serde_json::de::from_str::<LoginPatch>(&content) {
+        Ok(patch) => patch,
+        Err(e) => {
+            info!("Failed to parse a login update from request: {e}");
+            serve_status(request, 400);
+            return;
+        }
     };
+
+    if let Err(e) = db.update(id, patch) {
+        info!("Failed to update a login: {e}");
+        serve_status(request, 400);
+        return;
+    }
+
+    serve_status(request, 200);
 }
 
 // Now idempotent. Returns 204 on successful deletion, and 404 otherwise. Due to idempotency, a request can be sent multiple times by the client
@@ -377,7 +1054,182 @@ fn remove_login(request: Request, id: Option<&str>, db: &mut Database) {
         Response::from_string(StatusCode(204).default_reason_phrase()).with_status_code(204),
     ) {
         warn!("Failed to respond to a request: {e:#?}");
+    }
+}
+
+// The optional rows of a card, which are only shown if the login actually has them.
+fn card_details(login: &Login) -> String {
+    let mut rows = Vec::new();
+    if !login.urls.is_empty() {
+        rows.push((
+            String::from("Websites"),
+            login.urls.iter().map(|url| link(url)).join("<br />"),
+        ));
+    }
+    if !login.tags.is_empty() {
+        rows.push((
+            String::from("Tags"),
+            escape_html(&login.tags.iter().join(", ")),
+        ));
+    }
+    for field in &login.fields {
+        let value = match &field.value {
+            FieldValue::Text(text) => escape_html(text),
+            FieldValue::Hidden(secret) => escape_html(secret.expose_secret()),
+            FieldValue::Url(url) => link(url),
+            FieldValue::Date(date) => date.to_string(),
+        };
+        rows.push((escape_html(&field.name), value));
+    }
+    if !login.notes.is_empty() {
+        rows.push((
+            String::from("Notes"),
+            escape_html(&login.notes).replace('\n', "<br />"),
+        ));
+    }
+
+    let mut details = String::new();
+    for (label, value) in rows {
+        details.push_str(&format!(
+            include_str!("web/row.html"),
+            label = label,
+            value = value
+        ));
+    }
+    details
+}
+
+// URLs are checked when they're saved, but anything saved before that, or edited into the file by hand, is only shown
+// as text unless it's a website, so that e.g. a `javascript:` URL can't run anything.
+fn link(url: &str) -> String {
+    let is_website = Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+    if is_website {
+        format!(
+            r#"<a class="underline" href="{url}" rel="noreferrer">{url}</a>"#,
+            url = escape_html(url)
+        )
+    } else {
+        escape_html(url)
+    }
+}
+
+// Everything that ends up in a page comes from the user, so it needs escaping before it's formatted into the HTML.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Another site can't set the `Host` header, so checking it stops DNS rebinding, where the other site's own domain is
+// pointed at 127.0.0.1. Browsers add `Origin` to requests made from other sites, so that catches everything else. It
+// isn't required though, as clients other than browsers don't send it.
+fn check_origin(request: &Request, hosts: &[String]) -> Result<(), String> {
+    match header(request, "Host") {
+        Some(host) if hosts.iter().any(|allowed| same_host(allowed, host)) => {}
+        Some(host) => return Err(format!("its `Host` header was `{host}`")),
+        None => return Err(String::from("it had no `Host` header")),
+    }
+
+    if let Some(origin) = header(request, "Origin") {
+        let host = origin
+            .strip_prefix("http://")
+            .or_else(|| origin.strip_prefix("https://"));
+        if !host.is_some_and(|host| hosts.iter().any(|allowed| same_host(allowed, host))) {
+            return Err(format!("its `Origin` header was `{origin}`"));
+        }
+    }
+
+    Ok(())
+}
+
+// Browsers leave the port out when it's the default one, so e.g. `example.com` and `example.com:443` are the same.
+fn same_host(a: &str, b: &str) -> bool {
+    without_default_port(a).eq_ignore_ascii_case(without_default_port(b))
+}
+
+fn without_default_port(host: &str) -> &str {
+    host.strip_suffix(":443")
+        .or_else(|| host.strip_suffix(":80"))
+        .unwrap_or(host)
+}
+
+fn check_csrf_token(request: &Request, sessions: &Sessions) -> Result<(), String> {
+    let Some(expected) = session::token(request).and_then(|token| sessions.csrf_token(token))
+    else {
+        return Err(String::from("it had no session"));
+    };
+
+    match header(request, session::CSRF_HEADER_NAME) {
+        Some(token) if crypto::constant_time_eq(token.as_bytes(), expected.as_bytes()) => Ok(()),
+        Some(_) => Err(String::from("its CSRF token was wrong")),
+        None => Err(format!("it had no `{}` header", session::CSRF_HEADER_NAME)),
+    }
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+fn query_param(url: &Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|query| query.0 == name)
+        .map(|query| query.1.into_owned())
+}
+
+// Reads the body of a request, checking that it claims to be JSON. If it can't be read, the status code to respond
+// with is returned instead.
+fn read_json_body(request: &mut Request) -> Result<String, u16> {
+    let Some(content_type_header) = request
+        .headers()
+        .iter()
+        .find(|header| header.field.as_str() == "Content-Type")
+    else {
+        debug!(
+            "A request was made to `{}` without a `Content-Type` header",
+            request.url()
+        );
+        return Err(415);
     };
+
+    if content_type_header.value != "application/json" {
+        debug!(
+            "A request was made to `{}` without a valid `Content-Type` of `application/json`",
+            request.url()
+        );
+        return Err(415);
+    }
+
+    let mut buf: Vec<u8> = Vec::with_capacity(request.body_length().unwrap_or(0));
+    if let Err(e) = request.as_reader().read_to_end(&mut buf) {
+        info!("Could not read the body of the request: {e:#?}");
+        return Err(415);
+    }
+
+    String::from_utf8(buf).map_err(|e| {
+        debug!("The body of a request could not be interpreted as UTF-8: {e:#?}");
+        400
+    })
+}
+
+fn serve_status(request: Request, status: u16) {
+    let response =
+        Response::from_string(StatusCode(status).default_reason_phrase()).with_status_code(status);
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to respond to a request: {e:#?}");
+    }
 }
 
 fn serve_404(request: Request) {
@@ -0,0 +1,206 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use serde_derive::{Deserialize, Serialize};
+use url::Url;
+use zeroize::Zeroizing;
+
+use crate::secret::SecretString;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Kind {
+    // RFC 6238
+    Totp { period: u64 },
+    // RFC 4226. The counter is the one which will be used for the *next* code.
+    Hotp { counter: u64 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Otp {
+    // Base32 encoded, without padding, as that's how it's given to us in the first place.
+    secret: SecretString,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    pub kind: Kind,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OtpCode {
+    pub code: String,
+    // The number of seconds until a TOTP code expires. HOTP codes don't expire.
+    pub remaining: Option<u64>,
+    // The counter an HOTP code was generated with.
+    pub counter: Option<u64>,
+}
+
+impl Otp {
+    // Parses a Key URI, as described at https://github.com/google/google-authenticator/wiki/Key-Uri-Format, e.g.
+    // `otpauth://totp/Example:alice@example.com?secret=JBSWY3DPEHPK3PXP&issuer=Example`.
+    pub fn from_uri(uri: &str) -> Result<Self> {
+        let url = Url::parse(uri).wrap_err("Not a valid URI")?;
+        if url.scheme() != "otpauth" {
+            bail!("Expected an `otpauth://` URI");
+        }
+
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.into_owned())
+        };
+
+        let Some(secret) = param("secret") else {
+            bail!("The URI doesn't contain a secret");
+        };
+        let secret = normalise_secret(&secret);
+        BASE32_NOPAD
+            .decode(secret.as_bytes())
+            .wrap_err("The secret is not valid base32")?;
+
+        let algorithm = match param("algorithm").as_deref().map(str::to_ascii_uppercase) {
+            None => Algorithm::Sha1,
+            Some(algorithm) => match algorithm.as_str() {
+                "SHA1" => Algorithm::Sha1,
+                "SHA256" => Algorithm::Sha256,
+                "SHA512" => Algorithm::Sha512,
+                _ => bail!("Unsupported algorithm `{algorithm}`"),
+            },
+        };
+
+        let digits = match param("digits") {
+            None => 6,
+            Some(digits) => digits.parse().wrap_err("`digits` is not a number")?,
+        };
+        if !(6..=10).contains(&digits) {
+            bail!("`digits` must be between 6 and 10");
+        }
+
+        let kind = match url.host_str() {
+            Some("totp") => Kind::Totp {
+                period: match param("period") {
+                    None => 30,
+                    Some(period) => match period.parse() {
+                        Ok(0) | Err(_) => bail!("`period` must be a positive number"),
+                        Ok(period) => period,
+                    },
+                },
+            },
+            Some("hotp") => Kind::Hotp {
+                counter: param("counter")
+                    .ok_or_else(|| eyre!("HOTP URIs must contain a counter"))?
+                    .parse()
+                    .wrap_err("`counter` is not a number")?,
+            },
+            _ => bail!("Expected the type of one-time password to be either `totp` or `hotp`"),
+        };
+
+        Ok(Self {
+            secret: SecretString::new(secret),
+            algorithm,
+            digits,
+            kind,
+        })
+    }
+
+    // The inverse of `from_uri()`, for exporting to other programs.
+    pub fn to_uri(&self) -> String {
+        let algorithm = match self.algorithm {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        };
+        let (kind, parameter, value) = match self.kind {
+            Kind::Totp { period } => ("totp", "period", period),
+            Kind::Hotp { counter } => ("hotp", "counter", counter),
+        };
+        format!(
+            "otpauth://{kind}/Locket?secret={}&algorithm={algorithm}&digits={}&{parameter}={value}",
+            self.secret.expose_secret(),
+            self.digits
+        )
+    }
+
+    // Generates the current code. For HOTP this moves the counter on, so the database needs to be synced afterwards
+    // to make sure the same code is never handed out twice.
+    pub fn generate(&mut self) -> Result<OtpCode> {
+        match &mut self.kind {
+            Kind::Totp { period } => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .wrap_err("The system clock is set before 1970")?
+                    .as_secs();
+                let period = *period;
+
+                Ok(OtpCode {
+                    code: self.hotp(now / period)?,
+                    remaining: Some(period - now % period),
+                    counter: None,
+                })
+            }
+            Kind::Hotp { counter } => {
+                let current = *counter;
+                // Only an imported URI could start the counter anywhere near this high.
+                *counter = current
+                    .checked_add(1)
+                    .ok_or_else(|| eyre!("The HOTP counter can't go any higher"))?;
+
+                Ok(OtpCode {
+                    code: self.hotp(current)?,
+                    remaining: None,
+                    counter: Some(current),
+                })
+            }
+        }
+    }
+
+    // RFC 4226, section 5.3.
+    fn hotp(&self, counter: u64) -> Result<String> {
+        let key = Zeroizing::new(
+            BASE32_NOPAD
+                .decode(self.secret.expose_secret().as_bytes())
+                .wrap_err("The stored secret is not valid base32")?,
+        );
+        let msg = counter.to_be_bytes();
+
+        let hash = match self.algorithm {
+            Algorithm::Sha1 => mac::<Hmac<sha1::Sha1>>(&key, &msg),
+            Algorithm::Sha256 => mac::<Hmac<sha2::Sha256>>(&key, &msg),
+            Algorithm::Sha512 => mac::<Hmac<sha2::Sha512>>(&key, &msg),
+        };
+
+        let offset = usize::from(hash[hash.len() - 1] & 0xf);
+        let truncated = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        let code = u64::from(truncated) % 10u64.pow(self.digits);
+
+        Ok(format!("{code:0width$}", width = self.digits as usize))
+    }
+}
+
+fn mac<M: Mac + hmac::digest::KeyInit>(key: &[u8], msg: &[u8]) -> Vec<u8> {
+    let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(msg);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Authenticator apps are lenient about secrets, so we are too: they're often shown in lowercase, split into groups
+// with spaces, or padded.
+fn normalise_secret(secret: &str) -> String {
+    secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '=' && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
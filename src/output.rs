@@ -0,0 +1,166 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use color_eyre::eyre::{bail, Context, Result};
+use itertools::Itertools;
+use serde_derive::Serialize;
+use uuid::Uuid;
+
+use crate::args::Format;
+use crate::models::{CustomField, FieldValue, Login};
+
+// What a login looks like in structured output. This is deliberately separate from `Login`, so that things like the
+// password history and the one-time password secret never end up in a script's output by accident.
+#[derive(Serialize)]
+struct Record<'a> {
+    id: &'a Uuid,
+    name: &'a str,
+    username: &'a str,
+    password: &'a str,
+    urls: &'a [String],
+    notes: &'a str,
+    tags: &'a BTreeSet<String>,
+    fields: &'a [CustomField],
+}
+
+impl<'a> From<&(&'a Uuid, &'a Login)> for Record<'a> {
+    fn from((id, login): &(&'a Uuid, &'a Login)) -> Self {
+        Self {
+            id,
+            name: &login.name,
+            username: &login.username,
+            password: login.password.expose_secret(),
+            urls: &login.urls,
+            notes: &login.notes,
+            tags: &login.tags,
+            fields: &login.fields,
+        }
+    }
+}
+
+// The columns of CSV and TSV output. Custom fields don't fit into a fixed set of columns, so use JSON for those.
+static COLUMNS: [&str; 7] = [
+    "id", "name", "username", "password", "urls", "notes", "tags",
+];
+
+pub fn print_logins(logins: &[(&Uuid, &Login)], format: Format) -> Result<()> {
+    write_logins(io::stdout().lock(), logins, format)
+}
+
+pub fn write_logins<W: Write>(
+    mut writer: W,
+    logins: &[(&Uuid, &Login)],
+    format: Format,
+) -> Result<()> {
+    match format {
+        Format::Table => unreachable!("Tables are printed by `Database::query_with_args()`"),
+        Format::Json => {
+            let records: Vec<Record> = logins.iter().map_into().collect();
+            serde_json::to_writer_pretty(&mut writer, &records)
+                .wrap_err("Failed to write logins as JSON")?;
+            writeln!(writer).wrap_err("Failed to write the logins")?;
+        }
+        Format::Jsonl => {
+            for login in logins {
+                serde_json::to_writer(&mut writer, &Record::from(login))
+                    .wrap_err("Failed to write a login as JSON")?;
+                writeln!(writer).wrap_err("Failed to write the logins")?;
+            }
+        }
+        Format::Csv | Format::Tsv => {
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(if matches!(format, Format::Tsv) {
+                    b'\t'
+                } else {
+                    b','
+                })
+                .from_writer(writer);
+            writer
+                .write_record(COLUMNS)
+                .wrap_err("Failed to write the header")?;
+            for (id, login) in logins {
+                let values = COLUMNS
+                    .iter()
+                    .map(|column| field(id, login, column).expect("Every column is a known field"));
+                writer
+                    .write_record(values)
+                    .wrap_err("Failed to write a login")?;
+            }
+            writer.flush().wrap_err("Failed to write the logins")?;
+        }
+    }
+
+    Ok(())
+}
+
+// Prints every login using a template like `{name}\t{username}`. Any of the fields accepted by `field()` can be used,
+// `{{` and `}}` are literal braces, and `\t`, `\n` and `\\` are escapes, as they're a pain to type in a shell.
+pub fn print_template(logins: &[(&Uuid, &Login)], template: &str) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    for (id, login) in logins {
+        let line = render_template(id, login, template)?;
+        writeln!(stdout, "{line}").wrap_err("Failed to write to stdout")?;
+    }
+    Ok(())
+}
+
+fn render_template(id: &Uuid, login: &Login, template: &str) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                rendered.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                rendered.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => bail!("The template has a `{{` which is never closed"),
+                    }
+                }
+                let Some(value) = field(id, login, &name) else {
+                    bail!("`{{{name}}}` isn't a field of `{}`", login.name);
+                };
+                rendered.push_str(&value);
+            }
+            '\\' => match chars.next() {
+                Some('t') => rendered.push('\t'),
+                Some('n') => rendered.push('\n'),
+                Some(other) => rendered.push(other),
+                None => rendered.push('\\'),
+            },
+            _ => rendered.push(c),
+        }
+    }
+    Ok(rendered)
+}
+
+// Gets a single field of a login as text, either one of the built in ones, or a custom field with that name.
+pub fn field(id: &Uuid, login: &Login, name: &str) -> Option<String> {
+    let value = match name {
+        "id" => id.to_string(),
+        "name" => login.name.clone(),
+        "username" => login.username.clone(),
+        "password" => login.password.expose_secret().to_owned(),
+        "urls" => login.urls.join(" "),
+        "notes" => login.notes.clone(),
+        "tags" => login.tags.iter().join(","),
+        _ => {
+            let field = login.fields.iter().find(|field| field.name == name)?;
+            match &field.value {
+                FieldValue::Text(text) | FieldValue::Url(text) => text.clone(),
+                FieldValue::Hidden(secret) => secret.expose_secret().to_owned(),
+                FieldValue::Date(date) => date.to_string(),
+            }
+        }
+    };
+    Some(value)
+}
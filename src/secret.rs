@@ -0,0 +1,49 @@
+use std::fmt::{Debug, Display};
+
+use serde_derive::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+// A `String` which is wiped from memory when it's dropped, and which never prints its contents through `Debug` or
+// `Display`. The only way to get at the value is `expose_secret()`, so that every place a secret is actually used
+// is easy to find.
+//
+// It deliberately doesn't implement `Clone`, as every copy is another copy that needs to be wiped.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SecretString(String);
+
+impl SecretString {
+    #[must_use]
+    pub fn new(secret: String) -> Self {
+        Self(secret)
+    }
+
+    #[must_use]
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Debug for SecretString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretString(********)")
+    }
+}
+
+impl Display for SecretString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("********")
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use data_encoding::HEXLOWER;
+use rand::{rngs::OsRng, RngCore};
+use tiny_http::Request;
+
+static COOKIE_NAME: &str = "locket_session";
+// Unlike the session cookie, this one can be read by the page's scripts, which send it back in the `X-CSRF-Token`
+// header. Another site can make the browser send our cookies, but it can't read them, so it can't do the same.
+static CSRF_COOKIE_NAME: &str = "locket_csrf";
+pub static CSRF_HEADER_NAME: &str = "X-CSRF-Token";
+const TOKEN_LEN: usize = 32;
+
+// Everyone who has logged in to the web interface. Sessions only live in memory, so restarting the server logs
+// everybody out, which is no bad thing.
+pub struct Sessions {
+    // How long a session can go without being used before it has to log in again.
+    timeout: Duration,
+    sessions: HashMap<String, Session>,
+}
+
+struct Session {
+    last_seen: Instant,
+    csrf_token: String,
+}
+
+impl Sessions {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    // Starts a new session, returning its token and CSRF token, to put in their cookies.
+    pub fn create(&mut self) -> (String, String) {
+        // Nothing else ever clears out sessions that were just abandoned, so this is as good a time as any.
+        let timeout = self.timeout;
+        self.sessions
+            .retain(|_, session| session.last_seen.elapsed() < timeout);
+
+        let token = random_token();
+        let csrf_token = random_token();
+        self.sessions.insert(
+            token.clone(),
+            Session {
+                last_seen: Instant::now(),
+                csrf_token: csrf_token.clone(),
+            },
+        );
+        (token, csrf_token)
+    }
+
+    // Checks that the session exists and hasn't expired, and if so, counts this as using it.
+    pub fn touch(&mut self, token: &str) -> bool {
+        match self.sessions.get_mut(token) {
+            Some(session) if session.last_seen.elapsed() < self.timeout => {
+                session.last_seen = Instant::now();
+                true
+            }
+            Some(_) => {
+                self.sessions.remove(token);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn csrf_token(&self, token: &str) -> Option<&str> {
+        self.sessions
+            .get(token)
+            .map(|session| session.csrf_token.as_str())
+    }
+
+    pub fn remove(&mut self, token: &str) {
+        self.sessions.remove(token);
+    }
+}
+
+fn random_token() -> String {
+    let mut bytes = [0; TOKEN_LEN];
+    OsRng.fill_bytes(&mut bytes);
+    HEXLOWER.encode(&bytes)
+}
+
+// Finds the session token in the `Cookie` header of a request, if there is one.
+pub fn token(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .iter()
+        .filter(|header| header.field.equiv("Cookie"))
+        .flat_map(|header| header.value.as_str().split(';'))
+        .find_map(|cookie| {
+            cookie
+                .trim()
+                .strip_prefix(COOKIE_NAME)
+                .and_then(|rest| rest.strip_prefix('='))
+        })
+}
+
+// `HttpOnly` keeps the token away from any script on the page, `SameSite=Strict` stops other sites from making
+// requests with it, and `Secure` stops it from ever being sent without TLS.
+pub fn cookies(token: &str, csrf_token: &str) -> [String; 2] {
+    [
+        format!("{COOKIE_NAME}={token}; Secure; HttpOnly; SameSite=Strict; Path=/"),
+        format!("{CSRF_COOKIE_NAME}={csrf_token}; Secure; SameSite=Strict; Path=/"),
+    ]
+}
+
+pub fn expired_cookies() -> [String; 2] {
+    [
+        format!("{COOKIE_NAME}=; Secure; HttpOnly; SameSite=Strict; Path=/; Max-Age=0"),
+        format!("{CSRF_COOKIE_NAME}=; Secure; SameSite=Strict; Path=/; Max-Age=0"),
+    ]
+}
@@ -1,18 +1,22 @@
-use dialoguer::theme::Theme;
-use log::{debug, trace, warn};
+use log::{debug, error, trace};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
-use std::sync::{mpsc, Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
 use std::thread;
 use std::thread::JoinHandle;
 
 pub struct Threadpool {
     workers: Vec<Worker>,
     sender: Option<Sender<Job>>,
+    // How many jobs are waiting for a worker, not counting the ones being run.
+    queued: Arc<AtomicUsize>,
+    capacity: usize,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 impl Threadpool {
-    pub fn new(size: usize) -> Self {
+    pub fn new(size: usize, capacity: usize) -> Self {
         trace!("Initialising threadpool");
         assert!(size > 0, "size of thread pool must be greater than 0");
 
@@ -20,10 +24,11 @@ impl Threadpool {
         let (sender, reciever) = mpsc::channel();
 
         let receiver = Arc::new(Mutex::new(reciever));
+        let queued = Arc::new(AtomicUsize::new(0));
 
         for i in 0..size {
-            workers.push(Worker::new(i, Arc::clone(&receiver)));
-            trace!("Initialised thread {} of {size}", i + 1)
+            workers.push(Worker::new(i, Arc::clone(&receiver), Arc::clone(&queued)));
+            trace!("Initialised thread {} of {size}", i + 1);
         }
 
         debug!("Threadpool initialised");
@@ -31,27 +36,43 @@ impl Threadpool {
         Threadpool {
             workers,
             sender: Some(sender),
+            queued,
+            capacity,
         }
     }
 
+    // Whether the queue has reached its capacity, in which case any more jobs should be turned away rather than
+    // passed to `exec()`. Jobs are only ever added from one thread, so nothing can fill the queue in between.
+    pub fn is_full(&self) -> bool {
+        self.queued.load(Ordering::Acquire) >= self.capacity
+    }
+
     pub fn exec<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
         let job = Box::new(f);
 
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        self.queued.fetch_add(1, Ordering::AcqRel);
+        self.sender
+            .as_ref()
+            .expect("The sender is only taken when the pool is dropped")
+            .send(job)
+            .expect("The workers only stop once the sender is dropped");
     }
 }
 
 impl Drop for Threadpool {
     fn drop(&mut self) {
+        // Closing the channel lets the workers finish off whatever is left in the queue, and then stop.
+        drop(self.sender.take());
         for worker in &mut self.workers {
-            drop(self.sender.take());
             debug!("Shutting down worker {}", worker.id);
 
             if let Some(handle) = worker.handle.take() {
-                handle.join().unwrap();
+                if handle.join().is_err() {
+                    error!("Worker {} panicked", worker.id);
+                }
             }
 
             trace!("Shut down worker {}", worker.id);
@@ -65,25 +86,28 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>, queued: Arc<AtomicUsize>) -> Self {
         let handle = thread::Builder::new()
             .name(format!("Worker {id}"))
             .spawn(move || loop {
-                let message = receiver.lock().unwrap().recv();
-
-                match message {
-                    Ok(job) => {
-                        trace!("Worker {id} got a job; executing.");
-
-                        job();
-                    }
-                    Err(_) => {
-                        debug!("Worker {id} disconnected; shutting down.");
-                        break;
-                    }
+                let message = receiver
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .recv();
+
+                let Ok(job) = message else {
+                    debug!("Worker {id} disconnected; shutting down.");
+                    break;
+                };
+                queued.fetch_sub(1, Ordering::AcqRel);
+                trace!("Worker {id} got a job; executing.");
+
+                // One bad job shouldn't take a worker down with it, otherwise the pool would slowly run out.
+                if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                    error!("Worker {id} panicked while executing a job");
                 }
             })
-            .unwrap();
+            .expect("Failed to spawn a worker thread");
 
         Self {
             id,
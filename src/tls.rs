@@ -0,0 +1,119 @@
+use std::{fs, io::ErrorKind, io::Write, path::Path};
+
+use color_eyre::eyre::{bail, Context, Result};
+use data_encoding::BASE64;
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+use tiny_http::SslConfig;
+
+use crate::models::{self, Config};
+
+static CERT_FILE_NAME: &str = "cert.pem";
+static KEY_FILE_NAME: &str = "key.pem";
+
+// Loads the certificate and key given in the config, or if there aren't any, the self-signed pair in the data
+// directory, which are made the first time they're needed. The SHA-256 fingerprint of the certificate is returned
+// alongside, so that it can be shown to the user. The self-signed certificate is only made once, so it has to be
+// deleted to get one with any names that have been added to the config since.
+pub fn load(config: &Config, data_dir: &Path) -> Result<(SslConfig, String)> {
+    let (cert_path, key_path) = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert_path), Some(key_path)) => (cert_path.clone(), key_path.clone()),
+        (None, None) => {
+            let cert_path = data_dir.join(CERT_FILE_NAME);
+            let key_path = data_dir.join(KEY_FILE_NAME);
+            if !cert_path
+                .try_exists()
+                .wrap_err("Failed to check whether the certificate exists")?
+            {
+                create_self_signed(&cert_path, &key_path, subject_alt_names(config))
+                    .wrap_err("Failed to create a self-signed certificate")?;
+            }
+            (cert_path, key_path)
+        }
+        _ => bail!("`tls_cert` and `tls_key` have to be set together"),
+    };
+
+    let certificate = fs::read(&cert_path)
+        .wrap_err_with(|| format!("Failed to read the certificate at {}", cert_path.display()))?;
+    let private_key = fs::read(&key_path)
+        .wrap_err_with(|| format!("Failed to read the private key at {}", key_path.display()))?;
+    let fingerprint = fingerprint(&certificate)?;
+
+    Ok((
+        SslConfig {
+            certificate,
+            private_key,
+        },
+        fingerprint,
+    ))
+}
+
+// Browsers check the certificate against whatever name they used to reach the server, so it needs every one of them.
+fn subject_alt_names(config: &Config) -> Vec<String> {
+    let mut names = vec![String::from("localhost"), String::from("127.0.0.1")];
+    // Nobody can connect to `0.0.0.0` or `::` itself, those are reached through `allowed_hosts`.
+    if !config.bind.is_unspecified() {
+        names.push(config.bind.to_string());
+    }
+    names.extend(
+        config
+            .allowed_hosts
+            .iter()
+            .map(|host| String::from(without_port(host))),
+    );
+    names.into_iter().unique().collect()
+}
+
+// `allowed_hosts` can have a port on the end, e.g. `example.com:8443` or `[::1]:8443`, which isn't part of the name.
+fn without_port(host: &str) -> &str {
+    if let Some(rest) = host.strip_prefix('[') {
+        return rest.split_once(']').map_or(rest, |(ip, _)| ip);
+    }
+    match host.split_once(':') {
+        Some((name, port)) if !port.contains(':') => name,
+        _ => host,
+    }
+}
+
+fn create_self_signed(cert_path: &Path, key_path: &Path, names: Vec<String>) -> Result<()> {
+    let cert =
+        rcgen::generate_simple_self_signed(names).wrap_err("Failed to generate a certificate")?;
+
+    // Anything left over from a previous attempt is useless without its certificate.
+    if let Err(err) = fs::remove_file(key_path) {
+        if err.kind() != ErrorKind::NotFound {
+            return Err(err).wrap_err("Failed to remove the old private key");
+        }
+    }
+    models::create_private_file(key_path)
+        .wrap_err("Failed to create the private key file")?
+        .write_all(cert.serialize_private_key_pem().as_bytes())
+        .wrap_err("Failed to write the private key")?;
+    fs::write(
+        cert_path,
+        cert.serialize_pem()
+            .wrap_err("Failed to serialise the certificate")?,
+    )
+    .wrap_err("Failed to write the certificate")
+}
+
+// The fingerprint of the first certificate in a PEM file, in the `AB:CD:...` form that browsers show.
+fn fingerprint(pem: &[u8]) -> Result<String> {
+    let pem = std::str::from_utf8(pem).wrap_err("The certificate isn't in PEM format")?;
+    let Some(body) = pem
+        .split("-----BEGIN CERTIFICATE-----")
+        .nth(1)
+        .and_then(|rest| rest.split("-----END CERTIFICATE-----").next())
+    else {
+        bail!("The certificate file doesn't contain a PEM certificate");
+    };
+
+    let body: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+    let der = BASE64
+        .decode(body.as_bytes())
+        .wrap_err("The certificate isn't valid base64")?;
+    Ok(Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .join(":"))
+}
@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, Context, Result};
+use directories::ProjectDirs;
+use uuid::Uuid;
+
+use crate::lock::{self, Lock};
+use crate::models::{Config, Database, Login, LoginPatch};
+use crate::secret::SecretString;
+
+static CONFIG_FILE_NAME: &str = "locket.toml";
+static DATABASE_FILE_NAME: &str = "locket.db";
+
+/// The way into a vault, for anything that wants to use Locket as a library rather than through the CLI.
+pub struct Locket;
+
+/// What [`Locket::open()`] needs to know to open a vault.
+pub struct Options {
+    /// The config file, which says where the database is.
+    pub config_path: PathBuf,
+    /// The master password of the database.
+    pub password: SecretString,
+}
+
+/// An open vault. Nothing else can open it for as long as this is around, and the lock is released when it's
+/// dropped. Changes are only written to disk by [`Session::sync()`] or [`Session::close()`].
+// The fields are dropped in order, so the lock is let go of last.
+pub struct Session {
+    pub(crate) config: Config,
+    pub(crate) config_path: PathBuf,
+    pub(crate) db: Database,
+    _lock: Lock,
+}
+
+// The directories that the CLI keeps its files in.
+pub(crate) fn project_dirs() -> Result<ProjectDirs> {
+    let Some(proj_dirs) = ProjectDirs::from("com.github", "needlesslygrim", "Locket") else {
+        bail!("Failed to get project directories")
+    };
+    Ok(proj_dirs)
+}
+
+pub(crate) fn config_path(proj_dirs: &ProjectDirs) -> PathBuf {
+    proj_dirs.config_dir().join(CONFIG_FILE_NAME)
+}
+
+pub(crate) fn database_path(proj_dirs: &ProjectDirs) -> PathBuf {
+    proj_dirs.data_dir().join(DATABASE_FILE_NAME)
+}
+
+impl Locket {
+    /// Opens the vault that the config in `options` points to, locking it first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if Locket hasn't been initialised, if the vault is already open somewhere else, if the master
+    /// password is wrong, or if the config or database couldn't be read.
+    pub fn open(options: Options) -> Result<Session> {
+        Self::open_with(options, Database::open)
+    }
+
+    // For `locket migrate`, which is the only thing that opens a database from before encryption was added.
+    pub(crate) fn open_for_migration(options: Options) -> Result<Session> {
+        Self::open_with(options, Database::open_for_migration)
+    }
+
+    fn open_with(
+        options: Options,
+        open_db: impl FnOnce(&Path, &str) -> Result<Database>,
+    ) -> Result<Session> {
+        let config = Config::open(&options.config_path).wrap_err("Failed to open the config")?;
+        // The lock is taken before the database is read, so that nothing can change it in between us reading and
+        // writing it.
+        let lock =
+            Lock::acquire(&lock::path_for(&config.path)).wrap_err("Failed to lock the vault")?;
+        let mut db = open_db(&config.path, options.password.expose_secret())
+            .wrap_err("Failed to open the database")?;
+        db.history_limit = config.history_limit;
+
+        Ok(Session {
+            config,
+            config_path: options.config_path,
+            db,
+            _lock: lock,
+        })
+    }
+}
+
+impl Options {
+    /// Options for the vault that the CLI uses.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the home directory can't be found.
+    pub fn new(password: SecretString) -> Result<Self> {
+        Ok(Self {
+            config_path: config_path(&project_dirs()?),
+            password,
+        })
+    }
+}
+
+impl Session {
+    /// Adds a login, returning its ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if one of the login's URLs isn't valid.
+    pub fn add(&mut self, mut login: Login) -> Result<Uuid> {
+        login.normalise_urls()?;
+        Ok(self.db.add_login(login))
+    }
+
+    /// Finds the logins whose names fuzzily match `name`, best first, or every login if there's no `name`.
+    #[must_use]
+    pub fn query(&self, name: Option<&str>) -> Vec<(&Uuid, &Login)> {
+        self.db.query(name)
+    }
+
+    #[must_use]
+    pub fn get(&self, id: Uuid) -> Option<&Login> {
+        self.db.logins.get(&id)
+    }
+
+    /// Changes the fields of a login which are present in `patch`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there's no login with the ID, or if any of the new values aren't valid, in which case
+    /// nothing is changed.
+    pub fn update(&mut self, id: Uuid, patch: LoginPatch) -> Result<()> {
+        self.db.update(id, patch)
+    }
+
+    /// Removes a login, returning it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there's no login with the ID.
+    pub fn remove(&mut self, id: Uuid) -> Result<Login> {
+        let Some(login) = self.db.remove(id) else {
+            bail!("There is no login with the ID {id}");
+        };
+        Ok(login)
+    }
+
+    /// Writes the database to disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database couldn't be written.
+    pub fn sync(&mut self) -> Result<()> {
+        self.db.sync().wrap_err("Failed to sync database to disk")
+    }
+
+    /// Writes the database to disk, then releases the lock.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database couldn't be written. The lock is released either way.
+    pub fn close(mut self) -> Result<()> {
+        self.sync()
+    }
+
+    #[must_use]
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+}
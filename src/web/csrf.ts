@@ -0,0 +1,10 @@
+// The server sets this cookie when logging in, and expects it back in a header on anything which changes the
+// database. Other sites can't read our cookies, so they can't do the same.
+function csrf_token(): string {
+	const cookie = document.cookie
+		.split(';')
+		.map((cookie) => cookie.trim())
+		.find((cookie) => cookie.startsWith('locket_csrf='));
+
+	return cookie ? cookie.substring('locket_csrf='.length) : '';
+}
@@ -3,7 +3,15 @@ async function submit() {
 		new Login(
 			(<HTMLInputElement>document.getElementById('name')).value,
 			(<HTMLInputElement>document.getElementById('username')).value,
-			(<HTMLInputElement>document.getElementById('password')).value
+			(<HTMLInputElement>document.getElementById('password')).value,
+			(<HTMLInputElement>document.getElementById('url')).value
+				.split(/\s+/)
+				.filter((url) => url.length > 0),
+			(<HTMLTextAreaElement>document.getElementById('notes')).value,
+			(<HTMLInputElement>document.getElementById('tags')).value
+				.split(',')
+				.map((tag) => tag.trim())
+				.filter((tag) => tag.length > 0)
 		),
 	];
 
@@ -12,7 +20,10 @@ async function submit() {
 	let options: RequestInit = {
 		method: 'POST',
 		body: body,
-		headers: [['Content-Type', 'application/json']],
+		headers: [
+			['Content-Type', 'application/json'],
+			['X-CSRF-Token', csrf_token()],
+		],
 	};
 
 	let response = await fetch('/api/v1/new', options);
@@ -40,14 +51,49 @@ async function submit() {
 	window.alert('Failed to add a login.');
 }
 
+async function generate() {
+	let response = await fetch('/api/v1/generate');
+
+	if (!response.ok) {
+		console.log(response.status);
+		console.log(response.statusText);
+		window.alert('Failed to generate a password.');
+		return;
+	}
+
+	let generated: Generated = await response.json();
+	(<HTMLInputElement>document.getElementById('password')).value =
+		generated.password;
+	document.getElementById('entropy')!.textContent =
+		`${Math.round(generated.entropy)} bits of entropy`;
+}
+
+interface Generated {
+	password: string;
+	entropy: number;
+}
+
 class Login {
 	name: string;
 	username: string;
 	password: string;
+	urls: string[];
+	notes: string;
+	tags: string[];
 
-	constructor(name: string, username: string, password: string) {
+	constructor(
+		name: string,
+		username: string,
+		password: string,
+		urls: string[],
+		notes: string,
+		tags: string[]
+	) {
 		this.name = name;
 		this.username = username;
 		this.password = password;
+		this.urls = urls;
+		this.notes = notes;
+		this.tags = tags;
 	}
 }
@@ -9,6 +9,7 @@ async function remove_login(id: string) {
 
 	const res = await fetch(url, {
 		method: 'DELETE',
+		headers: [['X-CSRF-Token', csrf_token()]],
 	});
 
 	if (res.ok) {
@@ -35,3 +36,33 @@ async function remove_login(id: string) {
 	console.error(res.url);
 	window.alert('Failed to delete the login');
 }
+
+async function show_otp(id: string) {
+	let url: URL = new URL('/api/v1/otp', window.location.origin);
+	url.searchParams.append('id', id);
+
+	const res = await fetch(url, {
+		method: 'POST',
+		headers: [['X-CSRF-Token', csrf_token()]],
+	});
+
+	if (res.ok) {
+		const code: Code = await res.json();
+		let text = code.code;
+		if (code.remaining != null) {
+			text += ` (valid for ${code.remaining}s)`;
+		}
+		document.getElementById(`otp-${id}`)!.textContent = text;
+		return;
+	}
+
+	console.error(res.status);
+	console.error(res.url);
+	window.alert('Failed to get a one-time password');
+}
+
+interface Code {
+	code: string;
+	remaining: number | null;
+	counter: number | null;
+}